@@ -1,9 +1,98 @@
 use std::cmp;
+use std::collections::HashMap;
 use std::default::Default;
 use serde::{Serialize, Deserialize};
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
 
 use crate::config::{Int, Float, EPSILON};
 
+// max number of (value, weight) entries kept by the streaming quantile sketch
+const SKETCH_SIZE: usize = 4096;
+
+#[derive(Default, Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BinStrategy {
+    // thresholds at the weighted quantiles of the sorted values
+    #[default]
+    Quantile,
+    // thresholds evenly spaced between min and max
+    EqualWidth,
+    // weighted quantiles approximated by a streaming sketch, without sorting the whole column
+    Sketch,
+    // user-supplied thresholds
+    Manual(Vec<Float>)
+}
+
+/*
+a weighted quantile summary of bounded size. values are buffered, and once the buffer is full
+it is merged into the summary, which is compressed back to `capacity` entries of roughly equal
+weight. each entry keeps the largest value of the entries merged into it.
+*/
+#[derive(Default, Clone, Debug)]
+pub struct QuantileSketch {
+    pub capacity: usize,
+    summary: Vec<(Float, Float)>,
+    buffer: Vec<(Float, Float)>
+}
+
+impl QuantileSketch {
+
+    pub fn new(capacity: usize) -> QuantileSketch {
+        let capacity = cmp::max(capacity, 2);
+        QuantileSketch { capacity, summary: Vec::new(), buffer: Vec::with_capacity(capacity) }
+    }
+
+    pub fn insert(&mut self, v: Float, w: Float) {
+        self.buffer.push((v, w));
+        if self.buffer.len() >= self.capacity {
+            self.compress();
+        }
+    }
+
+    // (value, weight) pairs sorted by value
+    pub fn summary(&mut self) -> Vec<(Float, Float)> {
+        self.compress();
+        self.summary.clone()
+    }
+
+    fn compress(&mut self) {
+        if self.buffer.is_empty() {
+            return;
+        }
+
+        let mut merged: Vec<(Float, Float)> = Vec::with_capacity(self.summary.len() + self.buffer.len());
+        merged.append(&mut self.summary);
+        merged.append(&mut self.buffer);
+        merged.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+        if merged.len() <= self.capacity {
+            self.summary = merged;
+            return;
+        }
+
+        let total: Float = merged.iter().map(|x| x.1).sum();
+        let step = total / self.capacity as Float;
+
+        let mut summary: Vec<(Float, Float)> = Vec::with_capacity(self.capacity);
+        let mut acc: Float = 0.0;
+        let mut group: Float = 0.0;
+        for (v, w) in merged.iter() {
+            acc += w;
+            group += w;
+            if acc >= step * (summary.len() + 1) as Float {
+                summary.push((*v, group));
+                group = 0.0;
+            }
+        }
+
+        if group > 0.0 {
+            summary.push((merged.last().unwrap().0, group));
+        }
+
+        self.summary = summary;
+    }
+}
+
 
 #[derive(Default, Clone, Debug, Deserialize, Serialize)]
 pub struct ContinuousBin {
@@ -22,74 +111,114 @@ impl ContinuousBin {
         ContinuousBin {n_bin, threshold}
     }
 
-    pub fn fit(&mut self, arr: &Vec<Option<Float>>) {
-        let (indices, none_size) = ContinuousBin::sort(arr);
+    pub fn fit(&mut self, arr: &Vec<Option<Float>>, weight: &[Float], strategy: &BinStrategy) {
+        let threshold = match strategy {
+            BinStrategy::Quantile => {
+                let sorted = ContinuousBin::sort(arr, weight);
+                self.quantile(&sorted)
+            },
+            BinStrategy::Sketch => {
+                let mut sketch = QuantileSketch::new(SKETCH_SIZE);
+                for (v, w) in arr.iter().zip(weight.iter()) {
+                    if let Some(x) = v {
+                        sketch.insert(*x, *w);
+                    }
+                }
+                self.quantile(&sketch.summary())
+            },
+            BinStrategy::EqualWidth => self.equal_width(arr),
+            BinStrategy::Manual(value) => {
+                let mut threshold = value.clone();
+                threshold.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                threshold.dedup();
+                threshold
+            }
+        };
 
-        let total_size = indices.len();
-        let val_size = total_size - none_size;
-        let bin_size = val_size / self.n_bin;
+        if threshold.len() + 1 >= Int::MAX as usize {
+            panic! ("{} thresholds exceed the max number of bins {}", threshold.len(), Int::MAX - 1);
+        }
+
+        self.n_bin = threshold.len() + 1;
+        self.threshold = threshold;
+    }
 
+    /*
+    sorted: (value, weight) pairs sorted by value
+    each threshold is placed where the accumulated weight reaches i / n_bin of the total weight,
+    then moved forward to the next change of value
+    */
+    fn quantile(& self, sorted: &[(Float, Float)]) -> Vec<Float> {
         let mut threshold: Vec<Float> = Vec::new();
 
-        for i in 1..self.n_bin {
-            let mut bd = i * bin_size;
-            while bd < val_size {
-                if bd == val_size - 1 {break;}
-                let bd_next = bd + 1;
+        let n = sorted.len();
+        let total: Float = sorted.iter().map(|x| x.1).sum();
+        if n == 0 || total <= 0.0 {
+            return threshold;
+        }
 
-                let v = indices.get(bd).unwrap();
-                let v = arr[*v].unwrap();
+        let mut bd = 0;
+        let mut acc: Float = 0.0;
+        for i in 1..self.n_bin {
+            let target = total * i as Float / self.n_bin as Float;
+            while bd < n && acc + sorted[bd].1 <= target {
+                acc += sorted[bd].1;
+                bd += 1;
+            }
 
-                let v1 = indices.get(bd_next).unwrap();
-                let v1 = arr[*v1].unwrap();
+            let mut pos = bd;
+            while pos + 1 < n {
+                let v = sorted[pos].0;
+                let v1 = sorted[pos + 1].0;
 
                 if v < v1 - EPSILON {
-                    if threshold.len() == 0 || *threshold.last().unwrap() != v {
+                    if threshold.last() != Some(&v) {
                         threshold.push(v);
                     }
 
                     break;
                 }
 
-                bd += 1;
+                pos += 1;
             }
         }
 
-        self.n_bin = threshold.len() + 1;
-        self.threshold = threshold;
+        threshold
     }
 
-    pub fn map(& self, arr: &Vec<Option<Float>>) -> Vec<Option<Int>> {
-        arr.iter().map(|&v| self.discretize(v)).collect()
-    }
+    fn equal_width(& self, arr: &[Option<Float>]) -> Vec<Float> {
+        let values = arr.iter().filter_map(|&v| v);
+        let min = values.clone().fold(Float::INFINITY, Float::min);
+        let max = values.fold(Float::NEG_INFINITY, Float::max);
 
-    fn sort(arr: &Vec<Option<Float>>) -> (Vec<usize>, usize) {
-        let n = arr.len();
-        let mut indices: Vec<usize> = (0..n).collect();
+        let mut threshold: Vec<Float> = Vec::new();
+        if max.is_infinite() || max - min <= EPSILON {
+            return threshold;
+        }
 
-        indices.sort_by(|a, b| {
-            let av: Float = match arr[*a] {
-                Some(v) => v,
-                None => Float::INFINITY
-            };
+        let width = (max - min) / self.n_bin as Float;
+        for i in 1..self.n_bin {
+            let v = min + width * i as Float;
+            if threshold.last() != Some(&v) {
+                threshold.push(v);
+            }
+        }
 
-            let bv: Float = match arr[*b] {
-                Some(v) => v,
-                None => Float::INFINITY
-            };
+        threshold
+    }
 
-            av.partial_cmp(&bv).unwrap()
-        });
+    pub fn map(& self, arr: &Vec<Option<Float>>) -> Vec<Option<Int>> {
+        arr.iter().map(|&v| self.discretize(v)).collect()
+    }
 
-        let mut count = 0 as usize;
-        for idx in indices.iter() {
-            match arr[*idx] {
-                Some(_) => {},
-                None => count += 1
-            };
-        }
+    // (value, weight) pairs of the non-missing values, sorted by value
+    fn sort(arr: &[Option<Float>], weight: &[Float]) -> Vec<(Float, Float)> {
+        let mut sorted: Vec<(Float, Float)> = arr.iter().zip(weight.iter())
+            .filter_map(|(v, w)| v.map(|x| (x, *w)))
+            .collect();
 
-        (indices, count)
+        sorted.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        sorted
     }
 
     fn discretize(& self, v: Option<Float>) -> Option<Int> {
@@ -106,28 +235,42 @@ impl ContinuousBin {
 #[derive(Default, Clone, Debug, Deserialize, Serialize)]
 pub struct DiscreteBin {
     pub n_bin: usize,
-	pub mapping: Vec<Float>
+	pub mapping: Vec<Float>,
+    // bin of every value of the mapping, by DiscreteBin::key
+    #[serde(skip)]
+    index: HashMap<u32, usize>
 }
 
 impl DiscreteBin {
 
     pub fn new(n_bin: usize) -> DiscreteBin {
         let mapping: Vec<Float> = Vec::new();
-        DiscreteBin { n_bin, mapping }
+        DiscreteBin { n_bin, mapping, index: HashMap::new() }
+    }
+
+    // the bits of a value, with -0.0 turned into 0.0 so that the two equal values share a key
+    fn key(x: Float) -> u32 {
+        (x + 0.0).to_bits()
+    }
+
+    fn build_index(& self) -> HashMap<u32, usize> {
+        self.mapping.iter().enumerate().map(|(i, &x)| (DiscreteBin::key(x), i)).collect()
     }
 
     pub fn fit(&mut self, arr: &Vec<Option<Float>>) {
         let mut keys: Vec<Float> = Vec::new();
         let mut count: Vec<usize> = Vec::new();
+        let mut seen: HashMap<u32, usize> = HashMap::new();
         for v in arr.iter() {
             if v.is_none() {
                 continue;
             }
             let w = v.unwrap();
-            let pos = keys.iter().position(|&x| x == w);
+            let pos = seen.get(&DiscreteBin::key(w)).copied();
             match pos {
                 Some(x) => { count[x] = count[x] + 1;},
                 None => {
+                    seen.insert(DiscreteBin::key(w), keys.len());
                     keys.push(w);
                     count.push(1);
                 }
@@ -151,15 +294,26 @@ impl DiscreteBin {
 
         self.n_bin = n_bin;
         self.mapping = mapping;
+        self.index = self.build_index();
     }
 
     pub fn map(& self, arr: &Vec<Option<Float>>) -> Vec<Option<Int>> {
         let not_found_index = self.mapping.len();
 
+        // bins read from a model file are not indexed yet
+        let built;
+        let index = match self.index.len() == self.mapping.len() {
+            true => &self.index,
+            false => {
+                built = self.build_index();
+                &built
+            }
+        };
+
         arr.iter().map(|&v| {
             match v {
                 Some(x) => {
-                    let pos = self.mapping.iter().position(|&m| m == x);
+                    let pos = index.get(&DiscreteBin::key(x)).copied();
                     let index = match pos {
                         Some(y) => y as Int,
                         None => not_found_index as Int
@@ -173,3 +327,197 @@ impl DiscreteBin {
         }).collect()
     }
 }
+
+#[derive(Default, Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EncodeMethod {
+    // smoothed uplift of each category against control, binned as a continuous feature
+    #[default]
+    Uplift,
+    // category codes hashed into `n_bucket` buckets, binned as a categorical feature
+    Hash
+}
+
+#[derive(Default, Clone, Debug, Deserialize, Serialize)]
+pub struct CategoryEncoder {
+    pub method: EncodeMethod,
+    pub n_fold: usize,
+    pub smoothing: Float,
+    pub n_bucket: usize,
+    // of the fold assignment, from the entropy when None
+    #[serde(default)]
+    pub seed: Option<u64>,
+    pub keys: Vec<Float>,
+    pub score: Vec<Float>,
+    pub default: Float
+}
+
+impl CategoryEncoder {
+
+    pub fn new(method: EncodeMethod, n_fold: usize, smoothing: Float, n_bucket: usize,
+        seed: Option<u64>) -> CategoryEncoder {
+        CategoryEncoder { method, n_fold, smoothing, n_bucket, seed, keys: Vec::new(), score: Vec::new(), default: 0.0 }
+    }
+
+    pub fn iscat(& self) -> bool {
+        self.method == EncodeMethod::Hash
+    }
+
+    /*
+    fit the encoder and return the encoded training column. for uplift encoding, the score of
+    a training row is computed out-of-fold, so that a row's own response does not leak into its
+    feature value. the rows are dealt into folds of equal size in an order shuffled with the seed,
+    so that the folds do not follow the order of the input.
+    */
+    pub fn fit(&mut self, arr: &[Option<Float>], treatment: &[Int], y: &[Float], 
+        weight: &[Float]) -> Vec<Option<Float>> {
+
+        match self.method {
+            EncodeMethod::Uplift => self.fit_uplift(arr, treatment, y, weight),
+            EncodeMethod::Hash => self.map(arr)
+        }
+    }
+
+    pub fn map(& self, arr: &[Option<Float>]) -> Vec<Option<Float>> {
+        arr.iter().map(|&v| v.map(|x| self.encode(x))).collect()
+    }
+
+    pub fn encode(& self, x: Float) -> Float {
+        match self.method {
+            EncodeMethod::Uplift => match self.position(x) {
+                Some(pos) => self.score[pos],
+                None => self.default
+            },
+            EncodeMethod::Hash => CategoryEncoder::hash(x, self.n_bucket) as Float
+        }
+    }
+
+    /*
+    multiplicative (Lehmer) hash of the integer part of a category code. it only needs 64-bit
+    integer arithmetic, so the same bucket can be computed outside of this library.
+    */
+    pub fn hash(x: Float, n_bucket: usize) -> usize {
+        let modulus: u64 = 2147483647;
+        let key = (x.abs().trunc() as u64) % modulus;
+        let h = (key * 48271) % modulus;
+        (h % n_bucket as u64) as usize
+    }
+
+    fn position(& self, x: Float) -> Option<usize> {
+        self.keys.binary_search_by(|k| k.partial_cmp(&x).unwrap()).ok()
+    }
+
+    fn fit_uplift(&mut self, arr: &[Option<Float>], treatment: &[Int], y: &[Float], 
+        weight: &[Float]) -> Vec<Option<Float>> {
+
+        let mut keys: Vec<Float> = arr.iter().filter_map(|&v| v).collect();
+        keys.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        keys.dedup();
+        self.keys = keys;
+
+        let n_key = self.keys.len();
+        let n_arm = treatment.iter().map(|&t| t as usize + 1).max().unwrap_or(1);
+        let n_fold = cmp::max(self.n_fold, 1);
+
+        // [fold][key][arm] and [fold][arm] weighted sums of y and weights
+        let mut sum = vec![vec![vec![0.0; n_arm]; n_key]; n_fold];
+        let mut cnt = vec![vec![vec![0.0; n_arm]; n_key]; n_fold];
+        let mut global_sum = vec![vec![0.0; n_arm]; n_fold];
+        let mut global_cnt = vec![vec![0.0; n_arm]; n_fold];
+
+        let mut rng: StdRng = match self.seed {
+            None => StdRng::from_entropy(),
+            Some(v) => SeedableRng::seed_from_u64(v)
+        };
+        let mut folds: Vec<usize> = (0..arr.len()).map(|i| i % n_fold).collect();
+        folds.shuffle(&mut rng);
+
+        let mut rows: Vec<Option<usize>> = Vec::with_capacity(arr.len());
+        for (i, v) in arr.iter().enumerate() {
+            let f = folds[i];
+            let t = treatment[i] as usize;
+            global_sum[f][t] += y[i] * weight[i];
+            global_cnt[f][t] += weight[i];
+
+            let pos = v.and_then(|x| self.position(x));
+            if let Some(k) = pos {
+                sum[f][k][t] += y[i] * weight[i];
+                cnt[f][k][t] += weight[i];
+            }
+            rows.push(pos);
+        }
+
+        let total = |stat: &Vec<Vec<Vec<Float>>>| -> Vec<Vec<Float>> {
+            (0..n_key).map(|k| (0..n_arm).map(|t| stat.iter().map(|s| s[k][t]).sum()).collect()).collect()
+        };
+        let sum_all = total(&sum);
+        let cnt_all = total(&cnt);
+        let global_sum_all: Vec<Float> = (0..n_arm).map(|t| global_sum.iter().map(|s| s[t]).sum()).collect();
+        let global_cnt_all: Vec<Float> = (0..n_arm).map(|t| global_cnt.iter().map(|s| s[t]).sum()).collect();
+
+        let prior = CategoryEncoder::mean(&global_sum_all, &global_cnt_all);
+        self.score = (0..n_key).map(|k| self.uplift(&sum_all[k], &cnt_all[k], &prior)).collect();
+        self.default = self.uplift(&vec![0.0; n_arm], &vec![0.0; n_arm], &prior);
+
+        if n_fold == 1 {
+            return self.map(arr);
+        }
+
+        // priors and category statistics with each fold held out
+        let priors: Vec<Vec<Float>> = (0..n_fold).map(|f| {
+            let s: Vec<Float> = (0..n_arm).map(|t| global_sum_all[t] - global_sum[f][t]).collect();
+            let c: Vec<Float> = (0..n_arm).map(|t| global_cnt_all[t] - global_cnt[f][t]).collect();
+            CategoryEncoder::mean(&s, &c)
+        }).collect();
+
+        arr.iter().enumerate().map(|(i, v)| {
+            let f = folds[i];
+            v.map(|_| match rows[i] {
+                Some(k) => {
+                    let s: Vec<Float> = (0..n_arm).map(|t| sum_all[k][t] - sum[f][k][t]).collect();
+                    let c: Vec<Float> = (0..n_arm).map(|t| cnt_all[k][t] - cnt[f][k][t]).collect();
+                    self.uplift(&s, &c, &priors[f])
+                },
+                None => self.default
+            })
+        }).collect()
+    }
+
+    fn mean(sum: &[Float], cnt: &[Float]) -> Vec<Float> {
+        sum.iter().zip(cnt.iter()).map(|(&s, &c)| if c > 0.0 { s / c } else { 0.0 }).collect()
+    }
+
+    // average over the treatment arms of the smoothed mean response minus that of control
+    fn uplift(& self, sum: &[Float], cnt: &[Float], prior: &[Float]) -> Float {
+        let m = self.smoothing;
+        let mean: Vec<Float> = (0..prior.len()).map(|t| match cnt[t] + m {
+            d if d > 0.0 => (sum[t] + m * prior[t]) / d,
+            _ => prior[t]
+        }).collect();
+        let n_treatment = cmp::max(mean.len() - 1, 1) as Float;
+        mean.iter().skip(1).map(|x| x - mean[0]).sum::<Float>() / n_treatment
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shuffled_folds() {
+        // arms alternating with the rows and no uplift, so that folds taken in input order would
+        // each hold one arm and score an uplift of 1
+        let n = 1000;
+        let arr: Vec<Option<Float>> = vec![Some(3.0); n];
+        let treatment: Vec<Int> = (0..n).map(|i| (i % 2) as Int).collect();
+        let y = vec![1.0; n];
+        let weight = vec![1.0; n];
+
+        let mut encoder = CategoryEncoder::new(EncodeMethod::Uplift, 2, 1.0, 0, Some(1));
+        let encoded = encoder.fit(&arr, &treatment, &y, &weight);
+        assert!(encoded.iter().all(|x| x.unwrap().abs() < 0.1));
+
+        let mut again = CategoryEncoder::new(EncodeMethod::Uplift, 2, 1.0, 0, Some(1));
+        assert_eq!(again.fit(&arr, &treatment, &y, &weight), encoded);
+    }
+}
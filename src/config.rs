@@ -1,6 +1,13 @@
+use std::collections::BTreeMap;
 use serde::{Serialize, Deserialize};
 
+use crate::bin::{EncodeMethod, BinStrategy};
+
+#[cfg(not(feature = "wide-bin"))]
 pub type Int = u8;
+#[cfg(feature = "wide-bin")]
+pub type Int = u16;
+
 pub type Float = f32;
 pub const EPSILON: Float = f32::EPSILON;
 
@@ -39,8 +46,122 @@ pub struct TreeConfig {
 
     pub max_features: usize,
     pub max_depth: usize,
+    // the trees of the fitted forest, which are fewer than n_tree_configured when a fit stopped early
     pub n_tree: usize,
     pub subsample: Float,
     pub n_thread: usize,
-    pub seed: Option<u64>
+    pub seed: Option<u64>,
+
+    // categorical features are split into two sets of categories ordered by uplift,
+    // instead of one category against the rest
+    #[serde(default)]
+    pub cat_partition: bool,
+
+    // width in bits of the bin storage the model was trained with, see feature `wide-bin`
+    #[serde(default = "default_int_bits")]
+    pub int_bits: u32,
+
+    // high-cardinality categorical features encoded before binning, see `CategoryEncoder`
+    #[serde(default)]
+    pub encode_cols: Vec<String>,
+    #[serde(default)]
+    pub encode_method: EncodeMethod,
+    #[serde(default = "default_encode_n_fold")]
+    pub encode_n_fold: usize,
+    #[serde(default = "default_encode_smoothing")]
+    pub encode_smoothing: Float,
+    #[serde(default = "default_n_bucket")]
+    pub n_bucket: usize,
+
+    // binning of continuous features, which can be overridden per feature
+    #[serde(default)]
+    pub bin_strategy: BinStrategy,
+    #[serde(default)]
+    pub feature_bin_strategy: BTreeMap<String, BinStrategy>,
+
+    // the trees a fit grows, taken from n_tree at the first fit, see RandomForest::fit_monitored
+    #[serde(default)]
+    pub n_tree_configured: Option<usize>
+}
+
+impl TreeConfig {
+
+    /*
+    the error binning would panic with: bins, buckets and manual thresholds must fit in Int, one
+    value of which is left for the bin of the categories not seen in training
+    */
+    pub fn validate(& self) -> Result<(), String> {
+        let max = Int::MAX as usize;
+        if self.n_bin >= max {
+            return Err(format!("n_bin {} exceeds max {}, build with the `wide-bin` feature for more bins",
+                self.n_bin, max - 1));
+        }
+        if self.encode_method == EncodeMethod::Hash && self.n_bucket >= max {
+            return Err(format!("n_bucket {} exceeds max {}", self.n_bucket, max - 1));
+        }
+
+        let strategies = std::iter::once(&self.bin_strategy).chain(self.feature_bin_strategy.values());
+        for strategy in strategies {
+            if let BinStrategy::Manual(value) = strategy {
+                let mut threshold = value.clone();
+                threshold.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+                threshold.dedup();
+                if threshold.len() + 1 >= max {
+                    return Err(format!("{} thresholds exceed the max number of bins {}", threshold.len(), max - 1));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// model files written before the width was recorded were always trained with u8 bins
+fn default_int_bits() -> u32 {
+    u8::BITS
+}
+
+fn default_encode_n_fold() -> usize {
+    5
+}
+
+fn default_encode_smoothing() -> Float {
+    10.0
+}
+
+fn default_n_bucket() -> usize {
+    64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture;
+
+    #[test]
+    fn test_validate() {
+        let max = Int::MAX as usize;
+        let mut conf = fixture::conf("y", 1);
+        assert!(conf.validate().is_ok());
+
+        conf.n_bin = max;
+        assert!(conf.validate().is_err());
+        conf.n_bin = max - 1;
+        assert!(conf.validate().is_ok());
+
+        // buckets only bound the hash encoding
+        conf.n_bucket = max;
+        assert!(conf.validate().is_ok());
+        conf.encode_method = EncodeMethod::Hash;
+        assert!(conf.validate().is_err());
+        conf.n_bucket = 64;
+
+        // duplicate thresholds are binned once
+        let threshold: Vec<Float> = (0..max).map(|x| (x / 2) as Float).collect();
+        conf.feature_bin_strategy.insert("x0".to_string(), BinStrategy::Manual(threshold));
+        assert!(conf.validate().is_ok());
+        let threshold: Vec<Float> = (0..max - 1).map(|x| x as Float).collect();
+        conf.feature_bin_strategy.insert("x0".to_string(), BinStrategy::Manual(threshold));
+        assert!(conf.validate().is_err());
+    }
 }
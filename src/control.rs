@@ -0,0 +1,182 @@
+use std::fmt;
+use std::error::Error;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use serde::Serialize;
+
+use crate::config::{Int, Float};
+use crate::dmatrix::DMatrix;
+
+/*
+handle to a running fit or predict. it is cheap to clone and every clone shares the same flag,
+so one thread can cancel the work running on another
+*/
+#[derive(Clone, Default)]
+pub struct Control {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl Control {
+
+    pub fn new() -> Control {
+        Control::default()
+    }
+
+    pub fn cancel(& self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(& self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(& self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "cancelled")
+    }
+}
+
+impl Error for Cancelled {}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    Continue,
+    Stop
+}
+
+// a split taken while growing a tree, in the order the tree was grown
+#[derive(Clone, Debug, Serialize)]
+pub struct SplitRecord {
+    pub node: usize,
+    pub depth: usize,
+    pub feature: String,
+    pub iscat: bool,
+    pub value: Vec<Int>,
+    pub missing_left: bool,
+    pub gain: Float,
+    pub size: usize,
+}
+
+/*
+what the callback of a fit is told about each tree. oob is the transformed outcome mse of the
+trees grown so far on the rows left out of each tree, validation the same of the whole forest on
+the validation set, and splits is empty unless they are logged
+*/
+#[derive(Clone, Debug)]
+pub struct TreeInfo {
+    pub index: usize,
+    pub depth: usize,
+    pub n_leaves: usize,
+    pub elapsed: Duration,
+    pub oob: Option<Float>,
+    pub validation: Option<Float>,
+    pub splits: Vec<SplitRecord>,
+}
+
+/*
+callback run after each tree of a fit, in the order of the trees, on the thread that called fit.
+returning Action::Stop keeps the trees reported so far and starts no more
+*/
+pub struct FitMonitor<'a> {
+    pub oob: bool,
+    pub log_splits: bool,
+    // binned with the loader of the model
+    pub validation: Option<&'a DMatrix>,
+    pub early_stopping: Option<EarlyStopping>,
+    pub callback: Box<dyn FnMut(&TreeInfo) -> Action + 'a>,
+}
+
+impl<'a> FitMonitor<'a> {
+
+    pub fn new<F: FnMut(&TreeInfo) -> Action + 'a>(callback: F) -> FitMonitor<'a> {
+        FitMonitor {
+            oob: false,
+            log_splits: false,
+            validation: None,
+            early_stopping: None,
+            callback: Box::new(callback)
+        }
+    }
+
+    // early stopping watches the validation metric, or the oob metric when there is no validation set
+    pub fn needs_oob(& self) -> bool {
+        self.oob || (self.early_stopping.is_some() && self.validation.is_none())
+    }
+}
+
+impl Default for FitMonitor<'_> {
+    fn default() -> Self {
+        FitMonitor::new(|_| Action::Continue)
+    }
+}
+
+/*
+stops adding trees once the metric, lower being better, has not improved on its best value
+by more than min_delta for patience trees in a row
+*/
+#[derive(Clone, Copy, Debug)]
+pub struct EarlyStopping {
+    pub patience: usize,
+    pub min_delta: Float,
+    best: Option<Float>,
+    since_best: usize,
+}
+
+impl EarlyStopping {
+
+    // a patience of 0 would stop after the first tree, whatever the metric
+    pub fn new(patience: usize, min_delta: Float) -> Result<EarlyStopping, String> {
+        match patience {
+            0 => Err("patience must be at least 1".to_string()),
+            _ => Ok(EarlyStopping { patience, min_delta, best: None, since_best: 0 })
+        }
+    }
+
+    pub fn update(&mut self, metric: Option<Float>) -> Action {
+        let metric = match metric {
+            Some(x) => x,
+            None => return Action::Continue
+        };
+
+        match self.best {
+            Some(best) if metric >= best - self.min_delta => self.since_best += 1,
+            _ => {
+                self.best = Some(metric);
+                self.since_best = 0;
+            }
+        }
+
+        match self.since_best >= self.patience {
+            true => Action::Stop,
+            false => Action::Continue
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn actions(patience: usize, min_delta: Float, metrics: &[Option<Float>]) -> Vec<Action> {
+        let mut early_stopping = EarlyStopping::new(patience, min_delta).unwrap();
+        metrics.iter().map(|x| early_stopping.update(*x)).collect()
+    }
+
+    #[test]
+    fn test_patience() {
+        use Action::{Continue, Stop};
+
+        assert!(EarlyStopping::new(0, 0.0).is_err());
+        assert_eq!(actions(1, 0.0, &[Some(1.0), Some(0.9), Some(0.9)]), vec![Continue, Continue, Stop]);
+        assert_eq!(actions(2, 0.0, &[Some(1.0), Some(1.0), Some(0.5), Some(0.6), Some(0.6)]),
+            vec![Continue, Continue, Continue, Continue, Stop]);
+        // an improvement of min_delta or less is none, and a missing metric counts for nothing
+        assert_eq!(actions(1, 0.5, &[Some(1.0), Some(0.5)]), vec![Continue, Stop]);
+        assert_eq!(actions(1, 0.0, &[None, None, Some(1.0), None]), vec![Continue, Continue, Continue, Continue]);
+    }
+}
@@ -5,8 +5,8 @@ use std::error::Error;
 use std::cell::RefCell;
 use csv::Reader;
 
-use crate::config::{Int, Float};
-use crate::bin::{DiscreteBin, ContinuousBin};
+use crate::config::{Int, Float, TreeConfig};
+use crate::bin::{DiscreteBin, ContinuousBin, CategoryEncoder, BinStrategy};
 use crate::dmatrix::DMatrix;
 
 #[derive(Default, Clone, Debug)]
@@ -19,39 +19,130 @@ pub struct DataLoader {
     pub bins_disc: HashMap<String, DiscreteBin>,
     pub cats: Vec<String>,
     pub n_bin: usize,
+    pub encode: Vec<String>,
+    pub encoder: CategoryEncoder,
+    pub encoders: HashMap<String, CategoryEncoder>,
+    pub strategies: HashMap<String, BinStrategy>,
 }
 
 impl DataLoader {
 
-    pub fn new(features: Vec<String>, response: String, treatments: Vec<String>, 
-        weight: String, cats: Vec<String>, n_bin: usize, bins_cont: HashMap<String, ContinuousBin>,
-        bins_disc: HashMap<String, DiscreteBin>) -> DataLoader {
-        if n_bin >= (Int::MAX as usize) { 
-            panic! ("n_bin {} exceeds max {}", n_bin, Int::MAX);
+    pub fn from_config(conf: &TreeConfig, bins_cont: HashMap<String, ContinuousBin>, 
+        bins_disc: HashMap<String, DiscreteBin>, encoders: HashMap<String, CategoryEncoder>) -> DataLoader {
+
+        if let Err(e) = conf.validate() {
+            panic! ("{}", e);
+        }
+        let encoder = CategoryEncoder::new(conf.encode_method, conf.encode_n_fold, conf.encode_smoothing,
+            conf.n_bucket, conf.seed);
+
+        let mut strategies: HashMap<String, BinStrategy> = HashMap::new();
+        for name in conf.feature_cols.iter() {
+            let strategy = match conf.feature_bin_strategy.get(name) {
+                Some(x) => x.clone(),
+                None => conf.bin_strategy.clone()
+            };
+            strategies.insert(name.clone(), strategy);
+        }
+
+        DataLoader {
+            features: conf.feature_cols.clone(),
+            response: conf.y_col.clone(),
+            treatments: conf.treatment_cols.clone(),
+            weight: conf.weight_col.clone(),
+            bins_cont,
+            bins_disc,
+            cats: conf.cat_cols.clone(),
+            n_bin: conf.n_bin,
+            encode: conf.encode_cols.clone(),
+            encoder,
+            encoders,
+            strategies
+        }
+    }
+
+    /*
+    the error from_memory would panic with on these headers: encoders not fitted yet are fitted on
+    the rows, which needs the response and the treatment
+    */
+    pub fn validate(& self, headers: &[String]) -> Result<(), String> {
+        let unfitted = self.encode.iter().find(|x| self.features.contains(x) && !self.encoders.contains_key(*x));
+        let labelled = headers.contains(&self.response) && self.treatments.first().is_some_and(|x| headers.contains(x));
+        match unfitted {
+            Some(name) if !labelled => Err(format!("response and treatment are required to fit the encoder of {}", name)),
+            _ => Ok(())
         }
-        DataLoader {features, response, treatments, weight, bins_cont, bins_disc, cats, n_bin}
     }
 
     pub fn from_memory(&mut self, headers: &Vec<String>, indices: &Vec<Vec<String>>, 
         arr: &Vec<Vec<Option<Float>>>) -> DMatrix {
 
+        if let Err(e) = self.validate(headers) {
+            panic! ("{}", e);
+        }
         let feature_pos = DataLoader::find_pos(headers, &self.features, true);
         let response_pos = DataLoader::find_pos_single(headers, &self.response, false);
         let treatment_pos = DataLoader::find_pos(headers, &self.treatments, false);
         let weight_pos = DataLoader::find_pos_single(headers, &self.weight, false);
 
+        let mut response: Vec<Float> = Vec::new();
+        if !response_pos.is_none() {
+            let v = DataLoader::get_nth_column(arr, response_pos.unwrap());
+            response = v.into_iter().map(|option| option.unwrap()).collect();
+        }
+
+        let mut treatments: Vec<Vec<Int>> = Vec::new();
+        let mut treatment_size: Vec<usize> = Vec::new();
+        if treatment_pos.len() > 0 && !treatment_pos.get(0).unwrap().is_none() {
+            for pos in treatment_pos.iter() {
+                let v = DataLoader::get_nth_column(arr, pos.unwrap());
+                let v: Vec<Int> = v.into_iter().map(|option| option.unwrap() as Int).collect();
+                let size = v.iter().max().unwrap() + 1;
+                treatments.push(v);
+                treatment_size.push(size as usize);
+            }
+        }
+
+        let size = arr.len();
+        let weights: Vec<Float> = match weight_pos {
+            Some(pos) => {
+                let v = DataLoader::get_nth_column(arr, pos);
+                v.into_iter().map(|option| option.unwrap()).collect()
+            },
+            None => { vec![1.0; size] }
+        };
+
         let mut is_bool = Vec::new();
         for col in self.features.iter() {
-            let isin = self.cats.contains(col);
+            let isin = match self.encode.contains(col) {
+                x if x => self.encoder.iscat(),
+                _ => self.cats.contains(col)
+            };
             is_bool.push(isin);
         }
 
-        let mut feature: Vec<Vec<Option<Int>>> = Vec::new();
+        let mut feature: Vec<Int> = Vec::new();
         for (idx, pos) in feature_pos.iter().enumerate() {
             let p = pos.unwrap();
             let v = DataLoader::get_nth_column(arr, p);
             let name = self.features.get(idx).unwrap().clone();
 
+            // high-cardinality categories are first encoded into a numeric score or a bucket
+            let v = match self.encode.contains(& name) {
+                x if x => {
+                    match self.encoders.get(& name) {
+                        Some(encoder) => encoder.map(&v),
+                        None => {
+                            let mut encoder = self.encoder.clone();
+                            let encoded = encoder.fit(&v, &treatments[0], &response, &weights);
+                            self.encoders.insert(name.clone(), encoder);
+                            encoded
+                        }
+                    }
+                },
+                _ => v
+            };
+
             let iscat = is_bool.get(idx).unwrap();
             let vt = match iscat {
                 x if *x => {
@@ -65,8 +156,9 @@ impl DataLoader {
                 },
                 _ => {
                     if !self.bins_cont.contains_key(& name) {
+                        let strategy = self.strategies.get(& name).cloned().unwrap_or_default();
                         let mut bin = ContinuousBin::new(self.n_bin, None);
-                        bin.fit(&v);
+                        bin.fit(&v, &weights, &strategy);
                         self.bins_cont.insert(name.clone(), bin);
                     }
     
@@ -74,36 +166,9 @@ impl DataLoader {
                 }
             };
 
-            feature.push(vt);
-        }
-
-        let mut response: Vec<Float> = Vec::new();
-        if !response_pos.is_none() {
-            let v = DataLoader::get_nth_column(arr, response_pos.unwrap());
-            response = v.into_iter().map(|option| option.unwrap()).collect();
+            DMatrix::pack(&mut feature, &vt);
         }
 
-        let mut treatments: Vec<Vec<Int>> = Vec::new();
-        let mut treatment_size: Vec<usize> = Vec::new();
-        if treatment_pos.len() > 0 && !treatment_pos.get(0).unwrap().is_none() {
-            for pos in treatment_pos.iter() {
-                let v = DataLoader::get_nth_column(arr, pos.unwrap());
-                let v: Vec<Int> = v.into_iter().map(|option| option.unwrap() as Int).collect();
-                let size = v.iter().max().unwrap() + 1;
-                treatments.push(v);
-                treatment_size.push(size as usize);
-            }
-        }
-
-        let size = arr.len();
-        let weights: Vec<Float> = match weight_pos {
-            Some(pos) => {
-                let v = DataLoader::get_nth_column(arr, pos);
-                v.into_iter().map(|option| option.unwrap()).collect()
-            },
-            None => { vec![1.0; size] }
-        };
-
         let name = self.features.clone();
         let bin_size = self.get_bin_size();
         DMatrix::new(indices.clone(), feature, response, treatments, weights, is_bool, name, 
@@ -0,0 +1,197 @@
+use std::cmp::Ordering;
+
+use crate::config::{Int, Float};
+use crate::dmatrix::{DMatrix, MISSING};
+use crate::split_info::{SplitInfo, SplitInfoTrait};
+use crate::tree::{Tree, RegressionTree};
+
+// sums of a set of rows: weight, row count and weighted target of every arm
+#[derive(Clone)]
+struct Stat {
+    weight: f64,
+    count: usize,
+    sum: Vec<f64>,
+}
+
+impl Stat {
+
+    fn new(n_uplift: usize) -> Stat {
+        Stat { weight: 0.0, count: 0, sum: vec![0.0; n_uplift] }
+    }
+
+    fn add(&mut self, w: f64, target: &[Float]) {
+        self.weight += w;
+        self.count += 1;
+        for (s, t) in self.sum.iter_mut().zip(target.iter()) {
+            *s += w * *t as f64;
+        }
+    }
+
+    fn merge(&mut self, other: &Stat) {
+        self.weight += other.weight;
+        self.count += other.count;
+        for (s, o) in self.sum.iter_mut().zip(other.sum.iter()) {
+            *s += o;
+        }
+    }
+
+    fn minus(& self, other: &Stat) -> Stat {
+        Stat {
+            weight: self.weight - other.weight,
+            count: self.count - other.count,
+            sum: self.sum.iter().zip(other.sum.iter()).map(|(s, o)| s - o).collect()
+        }
+    }
+
+    // weighted sum of squares explained by the means, what a split is chosen to increase
+    fn score(& self) -> f64 {
+        match self.weight {
+            x if x > 0.0 => self.sum.iter().map(|s| s * s / x).sum(),
+            _ => 0.0
+        }
+    }
+
+    fn mean(& self) -> Vec<Float> {
+        self.sum.iter().map(|s| match self.weight {
+            x if x > 0.0 => (s / x) as Float,
+            _ => 0.0
+        }).collect()
+    }
+}
+
+struct Candidate {
+    gain: f64,
+    feature: usize,
+    iscat: bool,
+    value: Vec<Int>,
+    missing_left: bool,
+}
+
+// the best split of the rows on feature f, bins on the left when iscat is false being those <= value
+fn best_split(m: &DMatrix, target: &[Vec<Float>], rows: &[usize], f: usize, parent: &Stat,
+    min_samples_leaf: usize) -> Option<Candidate> {
+
+    let n_uplift = parent.sum.len();
+    let iscat = m.is_bool[f];
+    let column = m.column(f);
+
+    let n_bin = rows.iter().filter_map(|i| m.get(f, *i)).max().map(|x| x as usize + 1).unwrap_or(0);
+    let mut hist = vec![Stat::new(n_uplift); n_bin];
+    let mut missing = Stat::new(n_uplift);
+    for i in rows.iter() {
+        let w = m.weights[*i] as f64;
+        match column[*i] {
+            MISSING => missing.add(w, &target[*i]),
+            x => hist[x as usize].add(w, &target[*i])
+        }
+    }
+
+    // categories are ordered by their mean uplift, which makes the best prefix the best subset
+    let mut order: Vec<usize> = (0..n_bin).filter(|b| hist[*b].count > 0).collect();
+    if iscat {
+        let key = |b: &usize| hist[*b].mean().iter().sum::<Float>();
+        order.sort_by(|a, b| key(a).partial_cmp(&key(b)).unwrap_or(Ordering::Equal));
+    }
+
+    let mut best: Option<Candidate> = None;
+    let mut left = Stat::new(n_uplift);
+    for k in 0..order.len().saturating_sub(1) {
+        left.merge(&hist[order[k]]);
+
+        for missing_left in [false, true] {
+            let mut l = left.clone();
+            if missing_left {
+                l.merge(&missing);
+            }
+            let r = parent.minus(&l);
+            if l.count < min_samples_leaf || r.count < min_samples_leaf {
+                continue;
+            }
+
+            let gain = l.score() + r.score() - parent.score();
+            if gain > best.as_ref().map(|x| x.gain).unwrap_or(1e-12) {
+                let value = match iscat {
+                    true => order[..k + 1].iter().map(|b| *b as Int).collect(),
+                    false => vec![order[k] as Int]
+                };
+                best = Some(Candidate { gain, feature: f, iscat, value, missing_left });
+            }
+        }
+    }
+
+    best
+}
+
+/*
+a regression tree grown on target, the uplift of every row per treatment, by weighted least
+squares summed over the treatments. each node holds, with its number of rows, the mean of control,
+the outcome of every row under control, and for every treatment that mean plus the mean uplift, so
+that it predicts outcomes and uplifts like any uplift tree
+*/
+pub fn fit_surrogate(m: &DMatrix, target: &[Vec<Float>], control: &[Float], max_depth: usize,
+    min_samples_leaf: usize) -> RegressionTree {
+
+    let n_uplift = target.first().map(|x| x.len()).unwrap_or(0);
+    let mut tree = RegressionTree::new();
+    let mut stack: Vec<(usize, Vec<usize>)> = vec![(0, (0..m.size()).collect())];
+
+    while let Some((n, rows)) = stack.pop() {
+        let mut stat = Stat::new(n_uplift);
+        let mut base = Stat::new(1);
+        for i in rows.iter() {
+            stat.add(m.weights[*i] as f64, &target[*i]);
+            base.add(m.weights[*i] as f64, &control[*i..*i + 1]);
+        }
+
+        let c = base.mean()[0];
+        let mut summary: Vec<Vec<Float>> = vec![vec![c, stat.count as Float]];
+        summary.extend(stat.mean().iter().map(|u| vec![c + *u, stat.count as Float]));
+
+        let mut split_info = SplitInfo::new();
+        split_info.set_node_id(n);
+        split_info.set_summary(summary);
+
+        let candidate = match tree.depth(n) < max_depth {
+            true => (0..m.n_feature())
+                .filter_map(|f| best_split(m, target, &rows, f, &stat, min_samples_leaf))
+                .max_by(|a, b| a.gain.partial_cmp(&b.gain).unwrap_or(Ordering::Equal)),
+            false => None
+        };
+
+        let candidate = match candidate {
+            Some(x) => x,
+            None => {
+                tree.add_split(n, split_info);
+                continue;
+            }
+        };
+
+        split_info.set_feature_id(candidate.feature);
+        split_info.set_iscat(candidate.iscat);
+        split_info.set_missing_left(candidate.missing_left);
+        split_info.set_gain(candidate.gain as Float);
+        split_info.set_gain_importance(candidate.gain as Float);
+        split_info.set_value(Some(candidate.value.clone()));
+        tree.add_split(n, split_info);
+
+        let (mut left_rows, mut right_rows) = (Vec::new(), Vec::new());
+        for i in rows.into_iter() {
+            let left = match m.get(candidate.feature, i) {
+                None => candidate.missing_left,
+                Some(x) if candidate.iscat => candidate.value.contains(&x),
+                Some(x) => x <= candidate.value[0]
+            };
+            match left {
+                true => left_rows.push(i),
+                false => right_rows.push(i)
+            }
+        }
+
+        let l = tree.add_left(n);
+        let r = tree.add_right(n);
+        stack.push((r, right_rows));
+        stack.push((l, left_rows));
+    }
+
+    tree
+}
@@ -2,9 +2,14 @@ use std::collections::HashMap;
 
 use crate::config::{Float, Int};
 
+// marks a missing value in the binned feature buffer, bins never reach it
+pub const MISSING: Int = Int::MAX;
+
 pub struct DMatrix {
     pub indices: Vec<Vec<String>>,
-    pub feature: Vec<Vec<Option<Int>>>,
+    // binned features, column-major: feature f of row i is at f * n_row + i
+    pub feature: Vec<Int>,
+    pub n_row: usize,
     pub response: Vec<Float>,
     pub treatments: Vec<Vec<Int>>,
     pub weights: Vec<Float>,
@@ -16,13 +21,19 @@ pub struct DMatrix {
 
 impl DMatrix {
 
-    pub fn new(indices: Vec<Vec<String>>, feature: Vec<Vec<Option<Int>>>, response: Vec<Float>, 
+    pub fn new(indices: Vec<Vec<String>>, feature: Vec<Int>, response: Vec<Float>, 
         treatments: Vec<Vec<Int>>, weights: Vec<Float>, is_bool: Vec<bool>, 
         name: Vec<String>, bin_size: HashMap<String, usize>, treatment_size: Vec<usize>) -> DMatrix {
 
+        let n_row = match name.len() {
+            0 => 0,
+            n => feature.len() / n
+        };
+
         DMatrix {
             indices,
             feature,
+            n_row,
             response,
             treatments,
             weights,
@@ -33,32 +44,28 @@ impl DMatrix {
         }
     }
 
+    // appends a binned column to a feature buffer
+    pub fn pack(feature: &mut Vec<Int>, column: &[Option<Int>]) {
+        feature.extend(column.iter().map(|x| x.unwrap_or(MISSING)));
+    }
+
     pub fn size(& self) -> usize {
-        self.feature.get(0).unwrap().len()
+        self.n_row
     }
 
     pub fn n_feature(& self) -> usize {
-        self.feature.len()
+        self.name.len()
     }
     
     pub fn get(& self, feature_idx: usize, index: usize) -> Option<Int> {
-        self.feature[feature_idx][index]
+        match self.feature[feature_idx * self.n_row + index] {
+            x if x == MISSING => None,
+            x => Some(x)
+        }
     }
 
-    pub fn sort_index(& self, feature_idx: usize, indices: &mut [usize]) {
-        // make sure a, b are smaller than Int::MAX
-        indices.sort_by(|a, b| {
-            let av: Int = match self.feature[feature_idx][*a] {
-                Some(v) => v,
-                None => Int::MAX
-            };
-
-            let bv: Int = match self.feature[feature_idx][*b] {
-                Some(v) => v,
-                None => Int::MAX
-            };
-
-            av.partial_cmp(&bv).unwrap()
-        });
+    pub fn column(& self, feature_idx: usize) -> &[Int] {
+        let start = feature_idx * self.n_row;
+        &self.feature[start..start + self.n_row]
     }
 }
@@ -0,0 +1,262 @@
+use serde::Serialize;
+
+use crate::config::{Int, Float};
+use crate::bin::EncodeMethod;
+use crate::data_loader::DataLoader;
+use crate::dmatrix::DMatrix;
+use crate::split_info::SplitInfoTrait;
+use crate::tree::Tree;
+
+/*
+condition on the original value of a feature that sends a row down a branch, or puts it in a bin
+*/
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Condition {
+    Missing,
+    Less(Float),
+    GreaterEqual(Float),
+    // lower <= x < upper
+    Between(Float, Float),
+    // one of the values, or with other any value not seen in training
+    In { values: Vec<Float>, other: bool },
+    // the bucket of the category code, see CategoryEncoder::hash
+    Bucket { buckets: Vec<Float>, other: bool },
+}
+
+impl Condition {
+
+    // whether no value satisfies it
+    pub fn is_empty(& self) -> bool {
+        match self {
+            Condition::In { values, other } => values.is_empty() && !*other,
+            Condition::Bucket { buckets, other } => buckets.is_empty() && !*other,
+            _ => false
+        }
+    }
+
+    // the condition in words, such as "age < 25" or "city in {3, 7, other}"
+    pub fn describe(& self, feature: &str) -> String {
+        let set = |values: &[Float], other: bool| {
+            let mut items: Vec<String> = values.iter().map(|x| x.to_string()).collect();
+            if other {
+                items.push("other".to_string());
+            }
+            format!("{{{}}}", items.join(", "))
+        };
+
+        match self {
+            Condition::Missing => format!("{} is missing", feature),
+            Condition::Less(u) => format!("{} < {}", feature, u),
+            Condition::GreaterEqual(l) => format!("{} >= {}", feature, l),
+            Condition::Between(l, u) => format!("{} <= {} < {}", l, feature, u),
+            Condition::In { values, other } => format!("{} in {}", feature, set(values, *other)),
+            Condition::Bucket { buckets, other } => format!("bucket({}) in {}", feature, set(buckets, *other))
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Step {
+    pub node: usize,
+    pub feature: String,
+    pub condition: Condition,
+    // uplift of the child minus uplift of the node
+    pub contribution: Vec<Float>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TreePath {
+    pub tree: usize,
+    pub steps: Vec<Step>,
+    pub leaf: usize,
+    pub uplift: Vec<Float>,
+}
+
+/*
+why a row got its uplift. contributions follow the path of the row in every tree and give each
+feature the change of uplift along the splits on it, averaged over the trees, so that
+bias + sum of the contributions = uplift, bias being the average uplift at the roots
+*/
+#[derive(Clone, Debug, Serialize)]
+pub struct Explanation {
+    pub uplift: Vec<Float>,
+    pub bias: Vec<Float>,
+    pub contributions: Vec<(String, Vec<Float>)>,
+    pub paths: Vec<TreePath>,
+}
+
+// uplift of every treatment against the control at a node, from its summary
+pub fn node_uplift<S: SplitInfoTrait>(split_info: &S) -> Vec<Float> {
+    let summary = split_info.get_summary();
+    (1..summary.len()).map(|k| summary[k][0] - summary[0][0]).collect()
+}
+
+/*
+the branch of a split in original units. a continuous split is bin <= t, and the bin of x is the
+number of thresholds <= x, so the left branch is x < threshold[t]. categories of a categorical
+split are the values of the discrete bin, the last bin holding the values not seen in training
+*/
+pub fn decode(loader: &DataLoader, feature: &str, iscat: bool, value: &[Int], left: bool) -> Condition {
+    let encoder = loader.encoders.get(feature);
+
+    if iscat {
+        let bin = loader.bins_disc.get(feature).unwrap();
+        let bins: Vec<usize> = (0..bin.mapping.len() + 1).filter(|b| value.contains(&(*b as Int)) == left).collect();
+        let values: Vec<Float> = bins.iter().filter_map(|b| bin.mapping.get(*b).copied()).collect();
+        let other = bins.contains(&bin.mapping.len());
+
+        return match encoder {
+            Some(x) if x.method == EncodeMethod::Hash => Condition::Bucket { buckets: values, other },
+            _ => Condition::In { values, other }
+        };
+    }
+
+    let bin = loader.bins_cont.get(feature).unwrap();
+    let threshold = bin.threshold.get(value[0] as usize).copied().unwrap_or(Float::MAX);
+
+    match encoder {
+        // the bins of an uplift encoded feature are over the scores of its categories
+        Some(x) if x.method == EncodeMethod::Uplift => {
+            let values: Vec<Float> = x.keys.iter().zip(x.score.iter())
+                .filter(|(_, s)| (**s < threshold) == left)
+                .map(|(k, _)| *k)
+                .collect();
+            Condition::In { values, other: (x.default < threshold) == left }
+        },
+        _ => match left {
+            true => Condition::Less(threshold),
+            false => Condition::GreaterEqual(threshold)
+        }
+    }
+}
+
+/*
+number of bins of a fitted feature, the bins of a continuous x going from 0 to the number of
+thresholds, and those of a discrete one from 0 to the size of the mapping, the bin of values not
+seen in training, which n_bin leaves out when every category of training fits in the mapping
+*/
+pub fn n_bins(loader: &DataLoader, feature: &str) -> usize {
+    match loader.bins_disc.get(feature) {
+        Some(bin) => bin.mapping.len() + 1,
+        None => loader.bins_cont.get(feature).unwrap().threshold.len() + 1
+    }
+}
+
+/*
+the original values in some bins of a feature. the bins of a discrete feature are the
+values of its mapping, the last one holding the values not seen in training, and the bins of a
+continuous feature are taken as the range from the first to the last. the scores of an uplift
+encoded feature were out of fold in training, so the categories whose final score falls in the bins
+can be none
+*/
+pub fn decode_bins(loader: &DataLoader, feature: &str, bins: &[usize]) -> Condition {
+    let encoder = loader.encoders.get(feature);
+
+    if let Some(bin) = loader.bins_disc.get(feature) {
+        let values: Vec<Float> = bins.iter().filter_map(|b| bin.mapping.get(*b).copied()).collect();
+        let other = bins.contains(&bin.mapping.len());
+        return match encoder {
+            Some(x) if x.method == EncodeMethod::Hash => Condition::Bucket { buckets: values, other },
+            _ => Condition::In { values, other }
+        };
+    }
+
+    let threshold = &loader.bins_cont.get(feature).unwrap().threshold;
+    let lo = bins.iter().copied().min().unwrap_or(0);
+    let hi = bins.iter().copied().max().unwrap_or(threshold.len());
+    let lower = match lo {
+        0 => None,
+        _ => Some(threshold[lo - 1])
+    };
+    let upper = threshold.get(hi).copied();
+
+    match encoder {
+        Some(x) if x.method == EncodeMethod::Uplift => {
+            let inside = |s: Float| lower.is_none_or(|l| s >= l) && upper.is_none_or(|u| s < u);
+            let values: Vec<Float> = x.keys.iter().zip(x.score.iter())
+                .filter(|(_, s)| inside(**s))
+                .map(|(k, _)| *k)
+                .collect();
+            Condition::In { values, other: inside(x.default) }
+        },
+        _ => match (lower, upper) {
+            (Some(l), Some(u)) => Condition::Between(l, u),
+            (Some(l), None) => Condition::GreaterEqual(l),
+            (None, Some(u)) => Condition::Less(u),
+            (None, None) => Condition::Less(Float::MAX)
+        }
+    }
+}
+
+// walks the nodes of a tree from the root to the leaf of a row
+pub fn explain_tree<T: Tree>(tree: &T, index: usize, loader: &DataLoader, m: &DMatrix, row: usize) -> TreePath {
+    let left_children = tree.left_children();
+    let right_children = tree.right_children();
+    let splits = tree.splits();
+
+    let mut steps: Vec<Step> = Vec::new();
+    let mut n = 0;
+    while let (Some(l), Some(r)) = (left_children[n], right_children[n]) {
+        let split_info = splits[n].as_ref().unwrap();
+        let feature_id = split_info.get_feature_id();
+        let iscat = split_info.get_iscat();
+        let value = split_info.get_value().unwrap();
+
+        let x = m.get(feature_id, row);
+        let left = match x {
+            None => split_info.get_missing_left(),
+            Some(x) if iscat => value.contains(&x),
+            Some(x) => x <= value[0]
+        };
+
+        let feature = m.name[feature_id].clone();
+        let condition = match x {
+            None => Condition::Missing,
+            Some(_) => decode(loader, &feature, iscat, &value, left)
+        };
+
+        let child = match left {
+            true => l,
+            false => r
+        };
+        let before = node_uplift(split_info);
+        let after = node_uplift(splits[child].as_ref().unwrap());
+        let contribution = after.iter().zip(before.iter()).map(|(a, b)| a - b).collect();
+
+        steps.push(Step { node: n, feature, condition, contribution });
+        n = child;
+    }
+
+    let uplift = node_uplift(splits[n].as_ref().unwrap());
+    TreePath { tree: index, steps, leaf: n, uplift }
+}
+
+pub fn explain<T: Tree>(trees: &[&T], loader: &DataLoader, m: &DMatrix, row: usize) -> Explanation {
+    let paths: Vec<TreePath> = trees.iter().enumerate()
+        .map(|(k, tree)| explain_tree(*tree, k, loader, m, row))
+        .collect();
+
+    let n_tree = trees.len() as Float;
+    let n_uplift = paths.first().map(|x| x.uplift.len()).unwrap_or(0);
+    let mut uplift = vec![0.0; n_uplift];
+    let mut bias = vec![0.0; n_uplift];
+    let mut contributions: Vec<(String, Vec<Float>)> = m.name.iter().map(|x| (x.clone(), vec![0.0; n_uplift])).collect();
+
+    for (path, tree) in paths.iter().zip(trees.iter()) {
+        let root = node_uplift(tree.splits()[0].as_ref().unwrap());
+        for k in 0..n_uplift {
+            uplift[k] += path.uplift[k] / n_tree;
+            bias[k] += root[k] / n_tree;
+        }
+
+        for step in path.steps.iter() {
+            let feature_id = m.name.iter().position(|x| *x == step.feature).unwrap();
+            for k in 0..n_uplift {
+                contributions[feature_id].1[k] += step.contribution[k] / n_tree;
+            }
+        }
+    }
+
+    Explanation { uplift, bias, contributions, paths }
+}
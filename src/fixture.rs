@@ -0,0 +1,77 @@
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::config::{Float, TreeConfig};
+use crate::histogram::Histogram;
+use crate::strategy::Strategy;
+
+pub fn headers() -> Vec<String> {
+    ["x0", "x1", "c2", "t", "y", "yr"].iter().map(|x| x.to_string()).collect()
+}
+
+/*
+uplift data for the tests, as a table stores it, in doubles: x0 on a grid of hundredths, which
+floats mostly do not hold exactly, x1 uniform with missing values, c2 categorical on tenths, the
+treatment t, and a binary outcome y and a continuous one yr, both lifted by the treatment where x0
+is high or c2 is one of a few categories
+*/
+pub fn data(n: usize, seed: u64) -> Vec<Vec<Option<f64>>> {
+    let mut rng: StdRng = SeedableRng::seed_from_u64(seed);
+
+    (0..n).map(|_| {
+        let x0 = 19.0 + rng.gen_range(0, 200) as f64 / 100.0;
+        let x1: f64 = rng.gen();
+        let c2 = rng.gen_range(0, 8) as f64 / 10.0;
+        let t = rng.gen_range(0, 2) as f64;
+
+        let mut p = 0.3;
+        if t > 0.5 && (x0 > 19.99 || [0.1, 0.3, 0.7].contains(&c2)) {
+            p += 0.3;
+        }
+        let y = if rng.gen::<f64>() < p { 1.0 } else { 0.0 };
+        let yr = 10.0 * p + rng.gen::<f64>();
+
+        let x1 = if rng.gen::<f64>() < 0.2 { None } else { Some(x1) };
+        vec![Some(x0), x1, Some(c2), Some(t), Some(y), Some(yr)]
+    }).collect()
+}
+
+// the rows as predict takes them, every double rounded to a Float
+pub fn to_float(arr: &[Vec<Option<f64>>]) -> Vec<Vec<Option<Float>>> {
+    arr.iter().map(|row| row.iter().map(|x| x.map(|v| v as Float)).collect()).collect()
+}
+
+pub fn conf(y_col: &str, n_tree: usize) -> TreeConfig {
+    serde_json::from_value(serde_json::json!({
+        "index_cols": [], "feature_cols": ["x0", "x1", "c2"], "cat_cols": ["c2"],
+        "treatment_cols": ["t"], "y_col": y_col, "weight_col": "",
+        "n_bin": 16, "min_samples_leaf": 50, "min_samples_treatment": 10, "n_reg": 10, "alpha": 0.9,
+        "normalization": true, "max_features": 3, "max_depth": 5, "n_tree": n_tree, "subsample": 1.0,
+        "n_thread": 2, "seed": 1
+    })).unwrap()
+}
+
+/*
+the histogram of one feature with n_bin bins, the last one for missing values, as strategy fills it.
+every bin in 0..filled holds 100 rows of control and 100 of treatment, with a binary outcome that is
+positive 30% of the time, or 70% in treatment where the bin is uplifted
+*/
+pub fn histogram<S: Strategy>(strategy: &S, n_bin: usize, filled: usize, uplifted: &[usize]) -> Histogram {
+    let n_channel = strategy.n_channel();
+    let mut hist = Histogram::new(n_channel, 2, n_bin);
+    let mut value = vec![0.0; n_channel];
+
+    for bin in 0..filled {
+        for t in 0..2 {
+            let n_positive = if t == 1 && uplifted.contains(&bin) { 70 } else { 30 };
+            for i in 0..100 {
+                let y = if i < n_positive { 1.0 } else { 0.0 };
+                strategy.row_stat(y, 1.0, &mut value);
+                for (c, x) in value.iter().enumerate() {
+                    hist.data[(c * 2 + t) * n_bin + bin] += x;
+                }
+            }
+        }
+    }
+
+    hist
+}
@@ -0,0 +1,696 @@
+use std::fs::File;
+use std::io::prelude::*;
+use std::collections::HashMap;
+use memmap2::Mmap;
+use serde::{Serialize, Deserialize};
+
+use crate::tree::Tree;
+use crate::split_info::SplitInfoTrait;
+use crate::config::{Int, Float, TreeConfig};
+use crate::bin::{DiscreteBin, ContinuousBin, CategoryEncoder};
+use crate::data_loader::DataLoader;
+use crate::dmatrix::DMatrix;
+use crate::format::{ModelError, ModelHeader, checksum, checksum_parts};
+use crate::rf::MODEL_TYPE;
+
+// first bytes of a model file written in the binary format
+pub const BINARY_MAGIC: &[u8; 8] = b"CKBIN\0\0\0";
+
+pub const BINARY_VERSION: u32 = 1;
+
+const SECTION_HEADER_SIZE: usize = 20;
+const NODE_SIZE: usize = 16;
+const ENTRY_SIZE: usize = 16;
+
+const LEAF: u8 = 1;
+const CATEGORICAL: u8 = 2;
+const MISSING_LEFT: u8 = 4;
+
+/*
+one node in 16 bytes: the right child is always stored next to the left child, so only left is kept.
+value is the threshold bin of a continuous split, the offset of the categories of a categorical split,
+or the leaf index of a leaf
+*/
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FlatNode {
+    pub left: u32,
+    pub feature: u32,
+    pub value: u32,
+    pub count: u16,
+    pub flags: u8,
+}
+
+impl FlatNode {
+
+    pub fn is_leaf(& self) -> bool {
+        self.flags & LEAF != 0
+    }
+
+    pub fn iscat(& self) -> bool {
+        self.flags & CATEGORICAL != 0
+    }
+
+    pub fn missing_left(& self) -> bool {
+        self.flags & MISSING_LEFT != 0
+    }
+
+    fn write(& self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.left.to_le_bytes());
+        out.extend_from_slice(&self.feature.to_le_bytes());
+        out.extend_from_slice(&self.value.to_le_bytes());
+        out.extend_from_slice(&self.count.to_le_bytes());
+        out.push(self.flags);
+        out.push(0);
+    }
+
+    fn read(b: &[u8]) -> FlatNode {
+        FlatNode {
+            left: read_u32(b, 0),
+            feature: read_u32(b, 4),
+            value: read_u32(b, 8),
+            count: u16::from_le_bytes([b[12], b[13]]),
+            flags: b[14]
+        }
+    }
+}
+
+/*
+anything the nodes of a flattened tree can be read from, in memory or in a mapped file
+*/
+pub trait FlatSource {
+    fn node(& self, i: usize) -> FlatNode;
+    fn category(& self, i: usize) -> Int;
+    fn leaf_value(& self, leaf: usize, arm: usize) -> Float;
+    fn n_arm(& self) -> usize;
+
+    // index of the leaf reached when the binned value of feature f is value(f)
+    fn descend<F: Fn(usize) -> Option<Int>>(& self, value: F) -> usize {
+        let mut node = self.node(0);
+        while !node.is_leaf() {
+            let go_left = match value(node.feature as usize) {
+                None => node.missing_left(),
+                Some(x) if node.iscat() => {
+                    let start = node.value as usize;
+                    (start..start + node.count as usize).any(|i| self.category(i) == x)
+                },
+                Some(x) => (x as u32) <= node.value
+            };
+
+            let next = match go_left {
+                true => node.left,
+                false => node.left + 1
+            };
+            node = self.node(next as usize);
+        }
+
+        node.value as usize
+    }
+
+    // the same routing the partition does during training
+    fn route(& self, m: &DMatrix, row: usize) -> usize {
+        self.descend(|f| m.get(f, row))
+    }
+
+    // row holds the binned features in the order of the model features
+    fn route_row(& self, row: &[Option<Int>]) -> usize {
+        self.descend(|f| row[f])
+    }
+
+    fn predict(& self, m: &DMatrix) -> Vec<Vec<Float>> {
+        (0..m.size()).map(|row| self.leaf_uplift(self.route(m, row))).collect()
+    }
+
+    fn predict_row(& self, row: &[Option<Int>]) -> Vec<Float> {
+        self.leaf_uplift(self.route_row(row))
+    }
+
+    // expected response under the control and every treatment in a leaf
+    fn leaf_outcome(& self, leaf: usize) -> Vec<Float> {
+        (0..self.n_arm()).map(|k| self.leaf_value(leaf, k)).collect()
+    }
+
+    // uplift of every treatment against the control in a leaf
+    fn leaf_uplift(& self, leaf: usize) -> Vec<Float> {
+        let control = self.leaf_value(leaf, 0);
+        (1..self.n_arm()).map(|k| self.leaf_value(leaf, k) - control).collect()
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct FlatTree {
+    pub nodes: Vec<FlatNode>,
+    pub categories: Vec<Int>,
+    // outcome of every arm in every leaf, leaf-major
+    pub values: Vec<Float>,
+    pub n_arm: usize,
+    // node id in the source tree of every leaf, not written to the binary format
+    pub leaf_nodes: Vec<usize>,
+}
+
+impl FlatTree {
+
+    /*
+    nodes are renumbered breadth first, so the two children of a node are adjacent
+    */
+    pub fn compile<T: Tree>(tree: &T) -> FlatTree {
+        let left_children = tree.left_children();
+        let right_children = tree.right_children();
+        let splits = tree.splits();
+
+        let mut order: Vec<usize> = vec![0];
+        let mut left: Vec<usize> = Vec::new();
+        let mut i = 0;
+        while i < order.len() {
+            let n = order[i];
+            match (left_children[n], right_children[n]) {
+                (Some(l), Some(r)) => {
+                    left.push(order.len());
+                    order.push(l);
+                    order.push(r);
+                },
+                _ => left.push(0)
+            }
+            i += 1;
+        }
+
+        let mut flat = FlatTree::default();
+        let mut n_leaf = 0;
+        for (i, n) in order.iter().enumerate() {
+            let split_info = splits[*n].as_ref().unwrap();
+            let mut node = FlatNode { feature: split_info.get_feature_id() as u32, ..FlatNode::default() };
+
+            if left_children[*n].is_none() {
+                let summary = split_info.get_summary();
+                flat.n_arm = summary.len();
+                flat.values.extend(summary.iter().map(|x| x[0]));
+
+                node.value = n_leaf;
+                node.flags = LEAF;
+                flat.leaf_nodes.push(*n);
+                n_leaf += 1;
+                flat.nodes.push(node);
+                continue;
+            }
+
+            let value = split_info.get_value().unwrap();
+            node.left = left[i] as u32;
+            if split_info.get_iscat() {
+                node.value = flat.categories.len() as u32;
+                node.count = value.len() as u16;
+                node.flags |= CATEGORICAL;
+                flat.categories.extend(value);
+            } else {
+                node.value = value[0] as u32;
+            }
+
+            if split_info.get_missing_left() {
+                node.flags |= MISSING_LEFT;
+            }
+
+            flat.nodes.push(node);
+        }
+
+        flat
+    }
+
+    pub fn n_leaves(& self) -> usize {
+        self.nodes.iter().filter(|x| x.is_leaf()).count()
+    }
+
+    // depth of the deepest leaf, the root being at depth 0
+    pub fn depth(& self) -> usize {
+        let mut depth = vec![0; self.nodes.len()];
+        for (i, node) in self.nodes.iter().enumerate() {
+            if !node.is_leaf() {
+                let left = node.left as usize;
+                depth[left] = depth[i] + 1;
+                depth[left + 1] = depth[i] + 1;
+            }
+        }
+
+        depth.into_iter().max().unwrap_or(0)
+    }
+}
+
+impl FlatSource for FlatTree {
+
+    fn node(& self, i: usize) -> FlatNode {
+        self.nodes[i]
+    }
+
+    fn category(& self, i: usize) -> Int {
+        self.categories[i]
+    }
+
+    fn leaf_value(& self, leaf: usize, arm: usize) -> Float {
+        self.values[leaf * self.n_arm + arm]
+    }
+
+    fn n_arm(& self) -> usize {
+        self.n_arm
+    }
+}
+
+/*
+everything but the header and the trees, stored as json between them
+*/
+#[derive(Deserialize, Serialize)]
+struct BinaryMeta {
+    conf: TreeConfig,
+    bins_cont: Vec<(String, ContinuousBin)>,
+    bins_disc: Vec<(String, DiscreteBin)>,
+    encoders: Vec<(String, CategoryEncoder)>,
+}
+
+/*
+layout, little endian:
+    magic, version u32, header length u32, header json padded to 4 bytes,
+    then the checksummed part: meta length u32, meta json padded to 4 bytes,
+    and the section: n_tree, n_arm, n_node, n_category and n_value as u32,
+    per tree (node start, node count, category start, value start) as u32,
+    nodes, categories as u16 padded to 4 bytes, leaf values as f32
+*/
+pub fn write_binary(fname: &str, tree_type: &str, conf: &TreeConfig, loader: &DataLoader,
+    trees: &[FlatTree]) -> std::io::Result<()> {
+
+    let n_arm = trees.first().map(|x| x.n_arm).unwrap_or(0);
+    let n_node: usize = trees.iter().map(|x| x.nodes.len()).sum();
+    let n_category: usize = trees.iter().map(|x| x.categories.len()).sum();
+    let n_value: usize = trees.iter().map(|x| x.values.len()).sum();
+
+    let mut section: Vec<u8> = Vec::new();
+    for x in [trees.len(), n_arm, n_node, n_category, n_value] {
+        section.extend_from_slice(&(x as u32).to_le_bytes());
+    }
+
+    let (mut node_start, mut category_start, mut value_start) = (0, 0, 0);
+    for tree in trees.iter() {
+        for x in [node_start, tree.nodes.len(), category_start, value_start] {
+            section.extend_from_slice(&(x as u32).to_le_bytes());
+        }
+        node_start += tree.nodes.len();
+        category_start += tree.categories.len();
+        value_start += tree.values.len();
+    }
+
+    for tree in trees.iter() {
+        for node in tree.nodes.iter() {
+            node.write(&mut section);
+        }
+    }
+
+    for tree in trees.iter() {
+        for x in tree.categories.iter() {
+            section.extend_from_slice(&(*x as u16).to_le_bytes());
+        }
+    }
+    if n_category % 2 == 1 {
+        section.extend_from_slice(&[0, 0]);
+    }
+
+    for tree in trees.iter() {
+        for x in tree.values.iter() {
+            section.extend_from_slice(&x.to_le_bytes());
+        }
+    }
+
+    let features = &loader.features;
+    let meta = BinaryMeta {
+        conf: conf.clone(),
+        bins_cont: features.iter().filter_map(|x| loader.bins_cont.get(x).map(|b| (x.clone(), b.clone()))).collect(),
+        bins_disc: features.iter().filter_map(|x| loader.bins_disc.get(x).map(|b| (x.clone(), b.clone()))).collect(),
+        encoders: features.iter().filter_map(|x| loader.encoders.get(x).map(|e| (x.clone(), e.clone()))).collect(),
+    };
+
+    let padded = |mut json: Vec<u8>| {
+        while !json.len().is_multiple_of(4) {
+            json.push(b' ');
+        }
+        json
+    };
+
+    let meta = padded(serde_json::to_vec(&meta).unwrap());
+    let meta_len = (meta.len() as u32).to_le_bytes();
+    let mut header = ModelHeader::new(MODEL_TYPE, tree_type, trees.len(), loader, "");
+    header.checksum = checksum_parts(&[&meta_len, &meta, &section]);
+    let header = padded(serde_json::to_vec(&header).unwrap());
+
+    let mut file = File::create(fname)?;
+    file.write_all(BINARY_MAGIC)?;
+    file.write_all(&BINARY_VERSION.to_le_bytes())?;
+    file.write_all(&(header.len() as u32).to_le_bytes())?;
+    file.write_all(&header)?;
+    file.write_all(&meta_len)?;
+    file.write_all(&meta)?;
+    file.write_all(&section)?;
+
+    Ok(())
+}
+
+fn read_u32(b: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([b[offset], b[offset + 1], b[offset + 2], b[offset + 3]])
+}
+
+/*
+a forest scored straight from a memory-mapped binary model file, without building the trees
+*/
+pub struct MappedForest {
+    pub conf: TreeConfig,
+    pub loader: DataLoader,
+    pub header: ModelHeader,
+    mmap: Mmap,
+    n_tree: usize,
+    n_arm: usize,
+    n_node: usize,
+    n_category: usize,
+    n_value: usize,
+    section: usize,
+    nodes: usize,
+    categories: usize,
+    values: usize,
+}
+
+pub struct MappedTree<'a> {
+    bytes: &'a [u8],
+    n_arm: usize,
+    nodes: usize,
+    categories: usize,
+    values: usize,
+}
+
+impl<'a> FlatSource for MappedTree<'a> {
+
+    fn node(& self, i: usize) -> FlatNode {
+        let offset = self.nodes + i * NODE_SIZE;
+        FlatNode::read(&self.bytes[offset..offset + NODE_SIZE])
+    }
+
+    fn category(& self, i: usize) -> Int {
+        let offset = self.categories + i * 2;
+        u16::from_le_bytes([self.bytes[offset], self.bytes[offset + 1]]) as Int
+    }
+
+    fn leaf_value(& self, leaf: usize, arm: usize) -> Float {
+        let offset = self.values + (leaf * self.n_arm + arm) * 4;
+        Float::from_le_bytes([self.bytes[offset], self.bytes[offset + 1], self.bytes[offset + 2],
+            self.bytes[offset + 3]])
+    }
+
+    fn n_arm(& self) -> usize {
+        self.n_arm
+    }
+}
+
+impl MappedForest {
+
+    /*
+    verify hashes the meta and the tree section and checks every node points inside the file,
+    which costs one pass over the file; skip it only for files that are known to be intact
+    */
+    pub fn open(fname: &str, verify: bool) -> Result<MappedForest, ModelError> {
+        let file = File::open(fname)?;
+        // the file must not be modified while it is mapped
+        let mmap = unsafe { Mmap::map(&file)? };
+
+        let truncated = || ModelError::Parse("file is truncated".to_string());
+        if mmap.len() < 16 || &mmap[0..8] != BINARY_MAGIC {
+            return Err(ModelError::Parse("not a binary model file".to_string()));
+        }
+
+        let version = read_u32(&mmap, 8);
+        if version > BINARY_VERSION {
+            return Err(ModelError::Version(version));
+        }
+
+        let header_len = read_u32(&mmap, 12) as usize;
+        let checked = 16 + header_len;
+        if mmap.len() < checked + 4 {
+            return Err(truncated());
+        }
+
+        let meta_len = read_u32(&mmap, checked) as usize;
+        let section = checked + 4 + meta_len;
+        if mmap.len() < section + SECTION_HEADER_SIZE {
+            return Err(truncated());
+        }
+
+        let header: ModelHeader = serde_json::from_slice(&mmap[16..checked])?;
+        if header.int_bits != Int::BITS {
+            return Err(ModelError::Mismatch(format!("model was trained with {}-bit bins but this build \
+                uses {}-bit bins, toggle the `wide-bin` feature to load it", header.int_bits, Int::BITS)));
+        }
+
+        let n_tree = read_u32(&mmap, section) as usize;
+        let n_arm = read_u32(&mmap, section + 4) as usize;
+        let n_node = read_u32(&mmap, section + 8) as usize;
+        let n_category = read_u32(&mmap, section + 12) as usize;
+        let n_value = read_u32(&mmap, section + 16) as usize;
+        if n_tree != header.n_tree {
+            return Err(ModelError::Mismatch(format!("{} trees expected, {} found", header.n_tree, n_tree)));
+        }
+
+        let directory = section + SECTION_HEADER_SIZE;
+        let nodes = directory + n_tree * ENTRY_SIZE;
+        if mmap.len() < nodes {
+            return Err(truncated());
+        }
+
+        let categories = nodes + n_node * NODE_SIZE;
+        let values = categories + (n_category + n_category % 2) * 2;
+        if mmap.len() != values + n_value * 4 {
+            return Err(truncated());
+        }
+
+        // the meta is checked before it is parsed, a corrupted threshold giving wrong scores silently
+        if verify {
+            let found = checksum(&mmap[checked..]);
+            if found != header.checksum {
+                return Err(ModelError::Checksum { expected: header.checksum.clone(), found });
+            }
+        }
+
+        let meta: BinaryMeta = serde_json::from_slice(&mmap[checked + 4..section])?;
+        let bins_cont: HashMap<String, ContinuousBin> = meta.bins_cont.into_iter().collect();
+        let bins_disc: HashMap<String, DiscreteBin> = meta.bins_disc.into_iter().collect();
+        let encoders: HashMap<String, CategoryEncoder> = meta.encoders.into_iter().collect();
+        let loader = DataLoader::from_config(&meta.conf, bins_cont, bins_disc, encoders);
+        header.validate_model(n_tree, &loader)?;
+
+        let forest = MappedForest { conf: meta.conf, loader, header, mmap, n_tree, n_arm, n_node, n_category,
+            n_value, section, nodes, categories, values };
+
+        if verify {
+            forest.check_nodes()?;
+        }
+
+        Ok(forest)
+    }
+
+    fn check_nodes(& self) -> Result<(), ModelError> {
+        let n_feature = self.loader.features.len();
+        let mut start = (0, 0, 0);
+        for k in 0..self.n_tree {
+            let (node_start, n_node, category_start, value_start) = self.entry(k);
+            let (node_end, category_end, value_end) = self.tree_end(k);
+            if (node_start, category_start, value_start) != start || node_start + n_node != node_end
+                || category_end < category_start || value_end < value_start {
+                return Err(ModelError::Parse(format!("directory entry of tree {} is invalid", k)));
+            }
+            start = (node_end, category_end, value_end);
+
+            let n_category = category_end - category_start;
+            let n_leaf = (value_end - value_start) / self.n_arm.max(1);
+            let tree = self.tree(k);
+
+            for i in 0..n_node {
+                let node = tree.node(i);
+                let valid = match node {
+                    x if x.is_leaf() => (x.value as usize) < n_leaf,
+                    x if (x.left as usize) + 1 >= n_node || (x.left as usize) <= i => false,
+                    x if (x.feature as usize) >= n_feature => false,
+                    x if x.iscat() => (x.value as usize) + (x.count as usize) <= n_category,
+                    _ => true
+                };
+
+                if !valid {
+                    return Err(ModelError::Parse(format!("node {} of tree {} is invalid", i, k)));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn entry(& self, k: usize) -> (usize, usize, usize, usize) {
+        let offset = self.section + SECTION_HEADER_SIZE + k * ENTRY_SIZE;
+        let b = &self.mmap[..];
+        (read_u32(b, offset) as usize, read_u32(b, offset + 4) as usize, read_u32(b, offset + 8) as usize,
+            read_u32(b, offset + 12) as usize)
+    }
+
+    // where the nodes, categories and leaf values of a tree end, which is where the next tree starts
+    fn tree_end(& self, k: usize) -> (usize, usize, usize) {
+        match k + 1 {
+            x if x == self.n_tree => (self.n_node, self.n_category, self.n_value),
+            x => {
+                let (node_start, _, category_start, value_start) = self.entry(x);
+                (node_start, category_start, value_start)
+            }
+        }
+    }
+
+    pub fn n_tree(& self) -> usize {
+        self.n_tree
+    }
+
+    pub fn tree(& self, k: usize) -> MappedTree<'_> {
+        let (node_start, _, category_start, value_start) = self.entry(k);
+        MappedTree {
+            bytes: &self.mmap[..],
+            n_arm: self.n_arm,
+            nodes: self.nodes + node_start * NODE_SIZE,
+            categories: self.categories + category_start * 2,
+            values: self.values + value_start * 4,
+        }
+    }
+
+    // averaged uplift, same as RandomForest::predict
+    pub fn predict(& self, m: &DMatrix) -> Vec<Vec<Float>> {
+        let n = m.size();
+        let mut avg: Vec<Vec<Float>> = vec![vec![0.0; self.n_arm.saturating_sub(1)]; n];
+
+        for k in 0..self.n_tree {
+            let tree = self.tree(k);
+            for (row, score) in avg.iter_mut().enumerate() {
+                let uplift = tree.leaf_uplift(tree.route(m, row));
+                for (s, u) in score.iter_mut().zip(uplift.iter()) {
+                    *s = match k {
+                        0 => *u,
+                        _ => u + *s
+                    };
+                }
+            }
+        }
+
+        for score in avg.iter_mut() {
+            for s in score.iter_mut() {
+                *s /= self.n_tree as Float;
+            }
+        }
+
+        avg
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture;
+    use crate::rf::RandomForest;
+    use crate::tree::{Tree, ClassificationTree};
+    use crate::split_info::SplitInfoTrait;
+    use crate::kl::KLStrategy;
+
+    fn temp_file(name: &str) -> String {
+        std::env::temp_dir().join(format!("causalkit_{}_{}.bin", name, std::process::id())).to_str().unwrap().to_string()
+    }
+
+    fn fitted() -> RandomForest<ClassificationTree, KLStrategy> {
+        let mut rf: RandomForest<ClassificationTree, KLStrategy> = RandomForest::new(fixture::conf("y", 4));
+        let m = rf.loader.from_memory(&fixture::headers(), &Vec::new(), &fixture::to_float(&fixture::data(4000, 1)));
+        rf.fit(m);
+        rf
+    }
+
+    // uplift of a row from the nodes of the tree, as the partition routes rows during training
+    fn walk<T: Tree>(tree: &T, m: &DMatrix, row: usize) -> Vec<Float> {
+        let mut n = 0;
+        while let (Some(left), Some(right)) = (tree.left_children()[n], tree.right_children()[n]) {
+            let split_info = tree.splits()[n].as_ref().unwrap();
+            let value = split_info.get_value().unwrap();
+            let go_left = match m.get(split_info.get_feature_id(), row) {
+                None => split_info.get_missing_left(),
+                Some(x) if split_info.get_iscat() => value.contains(&x),
+                Some(x) => x <= value[0]
+            };
+            n = if go_left { left } else { right };
+        }
+
+        let summary = tree.splits()[n].as_ref().unwrap().get_summary();
+        summary.iter().skip(1).map(|x| x[0] - summary[0][0]).collect()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let rf = fitted();
+        let fname = temp_file("round_trip");
+        rf.save_binary(&fname).unwrap();
+        let mut forest = MappedForest::open(&fname, true).unwrap();
+        std::fs::remove_file(&fname).unwrap();
+
+        let headers = fixture::headers();
+        let rows = fixture::to_float(&fixture::data(1000, 2));
+        let mut loader = rf.loader.clone();
+        let expected = rf.predict(loader.from_memory(&headers, &Vec::new(), &rows));
+        let m = forest.loader.from_memory(&headers, &Vec::new(), &rows);
+        assert_eq!(forest.predict(&m), expected);
+    }
+
+    #[test]
+    fn test_corrupted_meta() {
+        let fname = temp_file("corrupted_meta");
+        fitted().save_binary(&fname).unwrap();
+        let mut bytes = std::fs::read(&fname).unwrap();
+
+        // one digit of the first threshold
+        let key = b"\"threshold\":[";
+        let at = bytes.windows(key.len()).position(|x| x == key).unwrap() + key.len();
+        let digit = at + bytes[at..].iter().position(|x| x.is_ascii_digit()).unwrap();
+        bytes[digit] = match bytes[digit] {
+            b'9' => b'8',
+            x => x + 1
+        };
+        std::fs::write(&fname, &bytes).unwrap();
+
+        let result = MappedForest::open(&fname, true);
+        std::fs::remove_file(&fname).unwrap();
+        assert!(matches!(result, Err(ModelError::Checksum { .. })));
+    }
+
+    #[test]
+    fn test_missing_and_unseen() {
+        let mut rf = fitted();
+        let fname = temp_file("missing_and_unseen");
+        rf.save_binary(&fname).unwrap();
+        let mut forest = MappedForest::open(&fname, true).unwrap();
+        std::fs::remove_file(&fname).unwrap();
+
+        // c2 takes a category the bins have not seen on some rows, and every feature is missing on others
+        let mut rows = fixture::data(1000, 2);
+        for (i, row) in rows.iter_mut().enumerate() {
+            match i % 5 {
+                0 => row[2] = Some(0.9),
+                1 => row[2] = None,
+                2 => row[0] = None,
+                _ => ()
+            }
+        }
+        let rows = fixture::to_float(&rows);
+
+        let headers = fixture::headers();
+        let m = rf.loader.from_memory(&headers, &Vec::new(), &rows);
+        let n_tree = rf.learners.len() as Float;
+        let expected: Vec<Vec<Float>> = (0..m.size()).map(|row| {
+            let mut sum = walk(&rf.learners[0].tree, &m, row);
+            for learn in rf.learners.iter().skip(1) {
+                sum = walk(&learn.tree, &m, row).iter().zip(sum.iter()).map(|(a, b)| a + b).collect();
+            }
+            sum.iter().map(|x| x / n_tree).collect()
+        }).collect();
+
+        let m = rf.loader.from_memory(&headers, &Vec::new(), &rows);
+        assert_eq!(rf.predict(m), expected);
+        let m = forest.loader.from_memory(&headers, &Vec::new(), &rows);
+        assert_eq!(forest.predict(&m), expected);
+    }
+}
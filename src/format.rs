@@ -0,0 +1,151 @@
+use std::fmt;
+use std::error::Error;
+use serde::{Serialize, Deserialize};
+
+use crate::config::Int;
+use crate::data_loader::DataLoader;
+
+// first line of a model file written in the versioned format
+pub const MAGIC: &str = "#causalkit-model";
+
+// 1 is the headerless format written by 0.1.x, which is still readable
+pub const FORMAT_VERSION: u32 = 2;
+
+#[derive(Debug)]
+pub enum ModelError {
+    Io(std::io::Error),
+    Parse(String),
+    Version(u32),
+    Mismatch(String),
+    Checksum { expected: String, found: String },
+}
+
+impl fmt::Display for ModelError {
+    fn fmt(& self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ModelError::Io(e) => write!(f, "failed to read model file: {}", e),
+            ModelError::Parse(msg) => write!(f, "malformed model file: {}", msg),
+            ModelError::Version(v) => write!(f, "model format version {} is not supported, \
+                the latest supported version is {}", v, FORMAT_VERSION),
+            ModelError::Mismatch(msg) => write!(f, "model file does not match: {}", msg),
+            ModelError::Checksum { expected, found } => write!(f, "model file is corrupted: \
+                checksum {} expected, {} found", expected, found),
+        }
+    }
+}
+
+impl Error for ModelError {}
+
+impl From<std::io::Error> for ModelError {
+    fn from(e: std::io::Error) -> ModelError {
+        ModelError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for ModelError {
+    fn from(e: serde_json::Error) -> ModelError {
+        ModelError::Parse(e.to_string())
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct FeatureSchema {
+    pub name: String,
+    // continuous, discrete or encoded
+    pub kind: String,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct ModelHeader {
+    pub format_version: u32,
+    pub crate_version: String,
+    pub model_type: String,
+    pub tree_type: String,
+    pub int_bits: u32,
+    pub n_tree: usize,
+    pub features: Vec<FeatureSchema>,
+    pub checksum: String,
+}
+
+impl ModelHeader {
+
+    pub fn new(model_type: &str, tree_type: &str, n_tree: usize, loader: &DataLoader, body: &str) -> ModelHeader {
+        ModelHeader {
+            format_version: FORMAT_VERSION,
+            crate_version: env!("CARGO_PKG_VERSION").to_string(),
+            model_type: model_type.to_string(),
+            tree_type: tree_type.to_string(),
+            int_bits: Int::BITS,
+            n_tree,
+            features: ModelHeader::schema(loader),
+            checksum: checksum(body.as_bytes()),
+        }
+    }
+
+    pub fn schema(loader: &DataLoader) -> Vec<FeatureSchema> {
+        loader.features.iter().map(|name| {
+            let kind = match name {
+                x if loader.encoders.contains_key(x) => "encoded",
+                x if loader.bins_disc.contains_key(x) => "discrete",
+                _ => "continuous"
+            };
+
+            FeatureSchema { name: name.clone(), kind: kind.to_string() }
+        }).collect()
+    }
+
+    // checks everything that can be checked before the body is parsed
+    pub fn validate(& self, model_type: &str, tree_type: &str, body: &str) -> Result<(), ModelError> {
+        if self.format_version > FORMAT_VERSION {
+            return Err(ModelError::Version(self.format_version));
+        }
+
+        if self.model_type != model_type || self.tree_type != tree_type {
+            return Err(ModelError::Mismatch(format!("{}<{}> expected, {}<{}> found", model_type, tree_type,
+                self.model_type, self.tree_type)));
+        }
+
+        if self.int_bits != Int::BITS {
+            return Err(ModelError::Mismatch(format!("model was trained with {}-bit bins but this build \
+                uses {}-bit bins, toggle the `wide-bin` feature to load it", self.int_bits, Int::BITS)));
+        }
+
+        let found = checksum(body.as_bytes());
+        if found != self.checksum {
+            return Err(ModelError::Checksum { expected: self.checksum.clone(), found });
+        }
+
+        Ok(())
+    }
+
+    // checks the parsed model against the header
+    pub fn validate_model(& self, n_tree: usize, loader: &DataLoader) -> Result<(), ModelError> {
+        if self.n_tree != n_tree {
+            return Err(ModelError::Mismatch(format!("{} trees expected, {} found", self.n_tree, n_tree)));
+        }
+
+        let features = ModelHeader::schema(loader);
+        if self.features != features {
+            return Err(ModelError::Mismatch(format!("feature schema {:?} expected, {:?} found",
+                self.features, features)));
+        }
+
+        Ok(())
+    }
+}
+
+// 64-bit FNV-1a hash of the model body, as a hex string
+pub fn checksum(body: &[u8]) -> String {
+    checksum_parts(&[body])
+}
+
+// the hash of the parts one after the other, the same as that of their concatenation
+pub fn checksum_parts(parts: &[&[u8]]) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for b in parts.iter().flat_map(|x| x.iter()) {
+        hash ^= *b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+
+    format!("{:016x}", hash)
+}
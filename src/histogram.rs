@@ -0,0 +1,240 @@
+use std::collections::HashMap;
+
+use crate::config::Float;
+use crate::dmatrix::{DMatrix, MISSING};
+
+/*
+per-bin statistics of one feature over the rows of a node, in a flat buffer laid out as
+[channel][treatment][bin]. bins are not accumulated and the last bin holds the missing values.
+what a channel holds is up to the strategy, see Strategy::row_stat
+*/
+#[derive(Clone, Debug, Default)]
+pub struct Histogram {
+    pub n_channel: usize,
+    pub n_treatment: usize,
+    pub n_bin: usize,
+    pub data: Vec<Float>,
+}
+
+impl Histogram {
+
+    pub fn new(n_channel: usize, n_treatment: usize, n_bin: usize) -> Histogram {
+        let data = vec![0.0; n_channel * n_treatment * n_bin];
+        Histogram { n_channel, n_treatment, n_bin, data }
+    }
+
+    // reuse the buffer of a histogram that is no longer needed
+    pub fn reset(&mut self, n_channel: usize, n_treatment: usize, n_bin: usize) {
+        self.n_channel = n_channel;
+        self.n_treatment = n_treatment;
+        self.n_bin = n_bin;
+        self.data.clear();
+        self.data.resize(n_channel * n_treatment * n_bin, 0.0);
+    }
+
+    pub fn get(& self, channel: usize, treatment: usize, bin: usize) -> Float {
+        self.data[(channel * self.n_treatment + treatment) * self.n_bin + bin]
+    }
+
+    pub fn row(& self, channel: usize, treatment: usize) -> &[Float] {
+        let start = (channel * self.n_treatment + treatment) * self.n_bin;
+        &self.data[start..start + self.n_bin]
+    }
+
+    /*
+    row_stat writes the value of every channel for a response and a weight
+    */
+    pub fn fill<F: Fn(Float, Float, &mut [Float])>(&mut self, m: &DMatrix, feature_id: usize, treatment_id: usize,
+        indices: &[usize], row_stat: F) {
+
+        let column = m.column(feature_id);
+        let treatment = &m.treatments[treatment_id];
+        let missing = self.n_bin - 1;
+        let mut value = vec![0.0; self.n_channel];
+
+        for v in indices.iter() {
+            let f = match column[*v] {
+                p if p == MISSING => missing,
+                p => p as usize
+            };
+
+            let t = treatment[*v] as usize;
+            row_stat(m.response[*v], m.weights[*v], &mut value);
+            for (c, x) in value.iter().enumerate() {
+                self.data[(c * self.n_treatment + t) * self.n_bin + f] += x;
+            }
+        }
+    }
+
+    /*
+    self = parent - child. differences that are tiny next to the parent are cancellation
+    error, and are set to zero so empty bins stay empty
+    */
+    pub fn subtract(&mut self, parent: &Histogram, child: &Histogram) {
+        self.reset(parent.n_channel, parent.n_treatment, parent.n_bin);
+        for (i, x) in self.data.iter_mut().enumerate() {
+            let p = parent.data[i];
+            let d = p - child.data[i];
+            *x = match d {
+                d if d.abs() <= p.abs() * 1e-6 => 0.0,
+                d => d
+            };
+        }
+    }
+
+    // [treatment][bin] of one channel, borrowed from the histogram
+    pub fn rows(& self, channel: usize) -> Vec<&[Float]> {
+        (0..self.n_treatment).map(|t| self.row(channel, t)).collect()
+    }
+}
+
+/*
+histograms of the nodes of the tree being grown, keyed by (node, feature).
+when a child needs a feature its parent has a histogram for, only the smaller of the two
+siblings is built from rows, and the larger is the parent minus the smaller
+*/
+#[derive(Clone, Debug, Default)]
+pub struct HistogramCache {
+    hists: HashMap<(usize, usize), Histogram>,
+    // child -> (parent, sibling)
+    family: HashMap<usize, (usize, usize)>,
+    pool: Vec<Histogram>,
+    // every histogram built from rows, none derived, which the derived ones must agree with
+    #[cfg(test)]
+    build_all: bool,
+}
+
+impl HistogramCache {
+
+    pub fn new() -> HistogramCache {
+        HistogramCache::default()
+    }
+
+    pub fn add_children(&mut self, node: usize, left: usize, right: usize) {
+        self.family.insert(left, (node, right));
+        self.family.insert(right, (node, left));
+    }
+
+    pub fn get(& self, node: usize, feature_id: usize) -> Option<&Histogram> {
+        self.hists.get(&(node, feature_id))
+    }
+
+    pub fn acquire(&mut self, n_channel: usize, n_treatment: usize, n_bin: usize) -> Histogram {
+        match self.pool.pop() {
+            Some(mut hist) => {
+                hist.reset(n_channel, n_treatment, n_bin);
+                hist
+            },
+            None => Histogram::new(n_channel, n_treatment, n_bin)
+        }
+    }
+
+    pub fn insert(&mut self, node: usize, feature_id: usize, hist: Histogram) {
+        if let Some(old) = self.hists.insert((node, feature_id), hist) {
+            self.pool.push(old);
+        }
+    }
+
+    /*
+    the parent and sibling of a node, if the parent has a histogram of the feature
+    */
+    pub fn derivable(& self, node: usize, feature_id: usize) -> Option<(usize, usize)> {
+        match self.family.get(&node) {
+            #[cfg(test)]
+            _ if self.build_all => None,
+            Some((parent, sibling)) if self.hists.contains_key(&(*parent, feature_id)) => Some((*parent, *sibling)),
+            _ => None
+        }
+    }
+
+    // stores the histogram built for one child and derives the other from the parent
+    pub fn insert_pair(&mut self, parent: usize, built: usize, derived: usize, feature_id: usize, hist: Histogram) {
+        let mut other = self.acquire(hist.n_channel, hist.n_treatment, hist.n_bin);
+        other.subtract(self.hists.get(&(parent, feature_id)).unwrap(), &hist);
+        self.insert(built, feature_id, hist);
+        self.insert(derived, feature_id, other);
+    }
+
+    // returns the buffers of a node to the pool once none of its children need them
+    pub fn release(&mut self, node: usize) {
+        let keys: Vec<(usize, usize)> = self.hists.keys().filter(|x| x.0 == node).cloned().collect();
+        for key in keys {
+            let hist = self.hists.remove(&key).unwrap();
+            self.pool.push(hist);
+        }
+    }
+
+    // once the second child of a node has been searched, the histograms of the node are not needed
+    pub fn searched(&mut self, node: usize) {
+        if let Some((parent, sibling)) = self.family.get(&node).cloned() {
+            if sibling < node {
+                self.release(parent);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::fixture;
+    use crate::config::TreeConfig;
+    use crate::data_loader::DataLoader;
+    use crate::learn::TreeLearn;
+    use crate::random::Random;
+    use crate::split_info::SplitInfoTrait;
+    use crate::strategy::Strategy;
+    use crate::tree::{Tree, ClassificationTree, RegressionTree};
+    use crate::kl::KLStrategy;
+    use crate::regression::RegressionStrategy;
+
+    fn grow<T, S>(m: &DMatrix, conf: &TreeConfig, build_all: bool) -> T
+        where T: Tree + Clone + Send, S: Strategy + Clone + Send {
+
+        let mut learn: TreeLearn<T, S> = TreeLearn::new(conf);
+        learn.cache.build_all = build_all;
+        let mut random = Random::new(Arc::new(Mutex::new(SeedableRng::seed_from_u64(1))));
+        learn.fit(m, conf, &mut random);
+        learn.tree
+    }
+
+    // the same tree grown with histograms derived by subtraction and with every one built from rows
+    fn check_splits<T, S>(y_col: &str, alpha: Float)
+        where T: Tree + Clone + Send, S: Strategy + Clone + Send {
+
+        let mut conf = fixture::conf(y_col, 1);
+        conf.alpha = alpha;
+        conf.max_depth = 8;
+        conf.min_samples_leaf = 20;
+        let mut loader = DataLoader::from_config(&conf, HashMap::new(), HashMap::new(), HashMap::new());
+        let m = loader.from_memory(&fixture::headers(), &Vec::new(), &fixture::to_float(&fixture::data(20000, 1)));
+
+        let derived: T = grow::<T, S>(&m, &conf, false);
+        let built: T = grow::<T, S>(&m, &conf, true);
+        assert!(derived.splits().len() > 15);
+        assert_eq!(derived.left_children(), built.left_children());
+
+        for (a, b) in derived.splits().iter().zip(built.splits().iter()) {
+            let (a, b) = (a.as_ref().unwrap(), b.as_ref().unwrap());
+            assert_eq!(a.get_feature_id(), b.get_feature_id());
+            assert_eq!(a.get_iscat(), b.get_iscat());
+            assert_eq!(a.get_value(), b.get_value());
+            assert_eq!(a.get_missing_left(), b.get_missing_left());
+            assert!((a.get_gain() - b.get_gain()).abs() <= 1e-4 * b.get_gain().abs().max(1.0));
+        }
+    }
+
+    #[test]
+    fn test_subtraction_classification() {
+        check_splits::<ClassificationTree, KLStrategy>("y", 0.9);
+    }
+
+    #[test]
+    fn test_subtraction_regression() {
+        // the penalty on unbalanced arms is small, so that the tree grows deep
+        check_splits::<RegressionTree, RegressionStrategy>("yr", 0.001);
+    }
+}
@@ -0,0 +1,93 @@
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rand::seq::SliceRandom;
+use rayon::prelude::*;
+use serde::Serialize;
+
+use crate::config::Float;
+use crate::dmatrix::DMatrix;
+use crate::flat::{FlatTree, FlatSource};
+use crate::metric::UpliftMetric;
+
+/*
+how much worse the metric gets when the values of a feature are shuffled among the rows, by
+repeat, with their mean and its standard error. unlike split gain it is not biased toward
+features with many bins, and it is measured on held out rows
+*/
+#[derive(Clone, Debug, Serialize)]
+pub struct Importance {
+    pub feature: String,
+    pub importance: Float,
+    pub std_error: Float,
+    pub drops: Vec<Float>,
+}
+
+// uplift of every row averaged over the trees, with the rows of feature f taken from perm
+fn score(trees: &[&FlatTree], m: &DMatrix, permuted: Option<(usize, &[usize])>) -> Vec<Vec<Float>> {
+    let n_tree = trees.len() as Float;
+
+    (0..m.size()).into_par_iter().map(|row| {
+        let mut sum: Vec<Float> = Vec::new();
+        for tree in trees.iter() {
+            let leaf = match permuted {
+                Some((feature, perm)) => tree.descend(|f| match f {
+                    x if x == feature => m.get(f, perm[row]),
+                    _ => m.get(f, row)
+                }),
+                None => tree.route(m, row)
+            };
+            let value = tree.leaf_uplift(leaf);
+            sum = match sum.len() {
+                0 => value,
+                _ => value.iter().zip(sum.iter()).map(|(a, b)| a + b).collect()
+            };
+        }
+
+        sum.iter().map(|x| x / n_tree).collect()
+    }).collect()
+}
+
+/*
+permutation importance of every feature of m, in the order of the model features. the features
+run in parallel on the current pool, and each permutation draws from its own generator seeded
+from seed, so the result does not depend on the scheduling. a metric that can not be computed,
+without control or treated rows, gives NaN
+*/
+pub fn permutation_importance(trees: &[&FlatTree], m: &DMatrix, metric: UpliftMetric, n_repeat: usize,
+    seed: Option<u64>) -> Vec<Importance> {
+
+    let mut rng: StdRng = match seed {
+        None => StdRng::from_entropy(),
+        Some(v) => SeedableRng::seed_from_u64(v)
+    };
+    let n_feature = m.n_feature();
+    let seeds: Vec<Vec<u64>> = (0..n_feature).map(|_| (0..n_repeat).map(|_| rng.gen()).collect()).collect();
+
+    let evaluate = |s: &[Vec<Float>]| metric.evaluate(m, s).unwrap_or(Float::NAN);
+    let base = evaluate(&score(trees, m, None));
+
+    (0..n_feature).into_par_iter().map(|f| {
+        let drops: Vec<Float> = seeds[f].iter().map(|seed| {
+            let mut rng: StdRng = SeedableRng::seed_from_u64(*seed);
+            let mut perm: Vec<usize> = (0..m.size()).collect();
+            perm.shuffle(&mut rng);
+
+            let permuted = evaluate(&score(trees, m, Some((f, &perm))));
+            match metric.higher_is_better() {
+                true => base - permuted,
+                false => permuted - base
+            }
+        }).collect();
+
+        let n = drops.len() as Float;
+        let importance = drops.iter().sum::<Float>() / n;
+        let std_error = match drops.len() {
+            x if x > 1 => {
+                let var = drops.iter().map(|x| (x - importance) * (x - importance)).sum::<Float>() / (n - 1.0);
+                (var / n).sqrt()
+            },
+            _ => 0.0
+        };
+
+        Importance { feature: m.name[f].clone(), importance, std_error, drops }
+    }).collect()
+}
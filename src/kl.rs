@@ -1,9 +1,12 @@
+use std::cmp;
+
 use crate::split_info::SplitInfo;
 use crate::config::{Int, EPSILON, TreeConfig};
 use crate::strategy::Strategy;
 use crate::config::Float;
 use crate::statistic::Count;
 use crate::dmatrix::DMatrix;
+use crate::histogram::Histogram;
 use crate::linalg;
 use crate::linalg::Matrix;
 
@@ -14,6 +17,7 @@ pub struct KLStrategy {
     n_reg: usize,
     alpha: Float,
     normalization: bool,
+    cat_partition: bool,
 }
 
 impl Strategy for KLStrategy {
@@ -25,50 +29,48 @@ impl Strategy for KLStrategy {
         let n_reg = conf.n_reg;
         let alpha = conf.alpha;
         let normalization = conf.normalization;
+        let cat_partition = conf.cat_partition;
+
+        KLStrategy { min_samples_leaf, min_samples_treatment, n_reg, alpha, normalization, cat_partition }
+    }
 
-        KLStrategy { min_samples_leaf, min_samples_treatment, n_reg, alpha, normalization }
+    fn n_channel(& self) -> usize {
+        2
     }
 
-    fn find_best_split(& self, m: &DMatrix, node_id: usize, feature_id: usize, 
-        indices: &mut [usize]) -> Self::T {
+    // the weight goes to the channel of the response, which is 0 or 1
+    fn row_stat(& self, y: Float, weight: Float, value: &mut [Float]) {
+        value[0] = 0.0;
+        value[1] = 0.0;
+        value[y as usize] = weight;
+    }
 
-        let iscat = m.is_bool.get(feature_id).unwrap().clone();
-        let name = m.name.get(feature_id).unwrap();
-        let feature_size = m.bin_size.get(name).unwrap().clone();
+    fn find_best_split_hist(& self, m: &DMatrix, node_id: usize, feature_id: usize, 
+        hist: &Histogram) -> Self::T {
+
+        let iscat = m.is_bool[feature_id];
         let treatment_id = 0;
-        let treatment_size = m.treatment_size.get(treatment_id).unwrap().clone();
 
-        let hist = Count::calculate(m, feature_id, iscat, treatment_id, indices,
-            feature_size, treatment_size);
+        let hist = Count::from_histogram(hist, feature_id, iscat, treatment_id);
 
-        let (value, gain, gain_importance, summary) = self.find_best_split_plain(&hist);
+        let (value, missing_left, gain, gain_importance, summary) = self.find_best_split_plain(&hist);
 
-        SplitInfo { node_id, feature_id, treatment_id, iscat, value, gain, gain_importance, summary }
+        SplitInfo { node_id, feature_id, treatment_id, iscat, value, missing_left, gain, gain_importance, summary }
     }
 }
 
 impl KLStrategy {
 
-    fn find_best_split_plain(& self, hist: &Count) -> (Option<Vec<Int>>, Float, Float, Vec<Vec<Float>>) {
+    fn find_best_split_plain(& self, hist: &Count) -> (Option<Vec<Int>>, bool, Float, Float, Vec<Vec<Float>>) {
         let mut best_gain = 0.0;
         let mut best_gain_importance = 0.0;
         let mut best_split_value = None;
+        let mut best_missing_left = false;
 
         let stat = & hist.stat;
         let mut parent_count = Vec::new();
-        for (_idx_y, arr2) in stat.iter().enumerate() {
-            let mut cnt = Vec::new();
-            for (_idx_t, arr1) in arr2.iter().enumerate() {
-                let c = match hist.iscat {
-                    x if x => {
-                        let v: Vec<f32> = arr1.iter().map(|&x| x as f32).collect();
-                        v.iter().sum::<f32>() as Float
-                    },
-                    _ => *arr1.last().unwrap()
-                };
-
-                cnt.push(c);
-            }
+        for arr2 in stat.iter() {
+            let cnt: Vec<Float> = arr2.iter().map(|arr1| arr1.iter().sum()).collect();
             parent_count.push(cnt);
         }
 
@@ -77,35 +79,47 @@ impl KLStrategy {
         let parent_score = KLStrategy::evaluation(parent.as_ref().unwrap());
 
         let n_splits = stat[0][0].len() - 1;
+        let missing_count = KLStrategy::get_missing_count(stat);
+        let has_missing = missing_count.iter().flatten().any(|&x| x > 0.0);
+        let matrix_parent = Matrix::new(&parent_count);
+        let matrix_missing = Matrix::new(&missing_count);
+
+        let candidates = match hist.iscat {
+            x if x && self.cat_partition => KLStrategy::get_partition_candidates(stat, parent.as_ref().unwrap(), self.n_reg),
+            x if x => (0..n_splits).map(|pos| (vec![pos as Int], KLStrategy::get_count(stat, pos))).collect(),
+            _ => KLStrategy::get_cumulative_candidates(stat)
+        };
 
-        for pos in 0..n_splits {
-            let left_count = KLStrategy::get_count(&stat, pos);
-
-            let matrix_parent = Matrix::new(&parent_count);
-            let matrix_left = Matrix::new(&left_count);
-            let matrix_right = matrix_parent.subtract(& matrix_left);
-            let right_count = matrix_right.get_data();
-
-            let result = self.calculate_gain(&left_count, right_count, &parent, 
-                parent_score);
-
-            if let Some(y) = result {
-                let (gain, gain_importance) = y;
-                if gain > best_gain {
-                    best_gain = gain;
-                    best_gain_importance = gain_importance;
+        for (value, left_count) in candidates.iter() {
+            // evaluate missing values going right first, so ties keep the default direction
+            let mut directions = vec![(false, Matrix::new(left_count))];
+            if has_missing {
+                directions.push((true, Matrix::new(left_count).add(&matrix_missing)));
+            }
 
-                    let mut value = Vec::new();
-                    value.push(pos as Int);                    
-                    best_split_value = Some(value);
+            for (missing_left, matrix_left) in directions.iter() {
+                let matrix_right = matrix_parent.subtract(matrix_left);
+                let right_count = matrix_right.get_data();
+
+                let result = self.calculate_gain(matrix_left.get_data(), right_count, &parent, 
+                    parent_score);
+
+                if let Some(y) = result {
+                    let (gain, gain_importance) = y;
+                    if gain > best_gain {
+                        best_gain = gain;
+                        best_gain_importance = gain_importance;
+                        best_missing_left = *missing_left;
+                        best_split_value = Some(value.clone());
+                    }
                 }
             }
         }
 
-        (best_split_value, best_gain, best_gain_importance, parent.unwrap())
+        (best_split_value, best_missing_left, best_gain, best_gain_importance, parent.unwrap())
     }
 
-    fn get_count(stat: &Vec<Vec<Vec<Float>>>, pos: usize) -> Vec<Vec<Float>> {
+    fn get_count(stat: &[Vec<&[Float]>], pos: usize) -> Vec<Vec<Float>> {
         let mut count = Vec::new();
         for (_idx_y, arr2) in stat.iter().enumerate() {
             let mut cnt = Vec::new();
@@ -118,6 +132,82 @@ impl KLStrategy {
         count
     }
 
+    // the bins up to every position as the left child, for continuous features
+    fn get_cumulative_candidates(stat: &[Vec<&[Float]>]) -> Vec<(Vec<Int>, Vec<Vec<Float>>)> {
+        let n_splits = stat[0][0].len() - 1;
+        let mut left: Vec<Vec<Float>> = stat.iter().map(|arr2| vec![0.0; arr2.len()]).collect();
+
+        (0..n_splits).map(|pos| {
+            for (idx_y, arr2) in stat.iter().enumerate() {
+                for (idx_t, arr1) in arr2.iter().enumerate() {
+                    left[idx_y][idx_t] += arr1[pos];
+                }
+            }
+            (vec![pos as Int], left.clone())
+        }).collect()
+    }
+
+    /*
+    order the categories by their regularized uplift against control, and return every prefix
+    of that order as a candidate left child: (categories, counts of the left child)
+    */
+    fn get_partition_candidates(stat: &[Vec<&[Float]>], parent: &Vec<Vec<Float>>, 
+        n_reg: usize) -> Vec<(Vec<Int>, Vec<Vec<Float>>)> {
+
+        let n_bin = stat[0][0].len() - 1;
+        let parent = Some(parent.clone());
+
+        let mut uplift: Vec<(Int, Float)> = Vec::new();
+        for pos in 0..n_bin {
+            let count = KLStrategy::get_count(stat, pos);
+            let total: Float = count.iter().flatten().sum();
+            if total <= 0.0 {
+                continue;
+            }
+
+            let summary = KLStrategy::count_reg(&count, &parent, 0, n_reg);
+            let p_c = summary[0][0];
+            let n_treatment = cmp::max(summary.len() - 1, 1);
+            let u: Float = summary.iter().skip(1).map(|x| x[0] - p_c).sum::<Float>() / n_treatment as Float;
+            uplift.push((pos as Int, u));
+        }
+
+        uplift.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(cmp::Ordering::Equal));
+
+        let mut candidates = Vec::new();
+        let mut value: Vec<Int> = Vec::new();
+        let mut left: Option<Matrix<Float>> = None;
+        for (pos, _) in uplift.iter() {
+            value.push(*pos);
+
+            let count = Matrix::new(&KLStrategy::get_count(stat, *pos as usize));
+            let acc = match left {
+                Some(x) => x.add(&count),
+                None => count
+            };
+
+            let mut categories = value.clone();
+            categories.sort();
+            candidates.push((categories, acc.get_data().clone()));
+            left = Some(acc);
+        }
+
+        candidates
+    }
+
+    /*
+    return the counts of the missing bin, which is the last slot of the histogram
+    */
+    fn get_missing_count(stat: &[Vec<&[Float]>]) -> Vec<Vec<Float>> {
+        let mut count = Vec::new();
+        for arr2 in stat.iter() {
+            let cnt: Vec<Float> = arr2.iter().map(|arr1| arr1[arr1.len() - 1]).collect();
+            count.push(cnt);
+        }
+
+        count
+    }
+
     /*
     return [[p, n]]    p: prob of positive; n: total count
     */
@@ -312,3 +402,40 @@ impl KLStrategy {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture;
+
+    fn split(cat_partition: bool, iscat: bool, hist: &Histogram) -> (Option<Vec<Int>>, bool) {
+        let mut conf = fixture::conf("y", 1);
+        conf.cat_partition = cat_partition;
+        let strategy = KLStrategy::new(&conf);
+
+        let (value, missing_left, _, _, _) = strategy.find_best_split_plain(&Count::from_histogram(hist, 0, iscat, 0));
+        (value, missing_left)
+    }
+
+    #[test]
+    fn test_missing_left() {
+        let strategy = KLStrategy::new(&fixture::conf("y", 1));
+
+        // the missing values are uplifted like the first bin, and join it on the left
+        let hist = fixture::histogram(&strategy, 4, 4, &[0, 3]);
+        assert_eq!(split(false, false, &hist), (Some(vec![0]), true));
+
+        let hist = fixture::histogram(&strategy, 4, 4, &[0]);
+        assert_eq!(split(false, false, &hist), (Some(vec![0]), false));
+    }
+
+    #[test]
+    fn test_partition() {
+        let strategy = KLStrategy::new(&fixture::conf("y", 1));
+        let hist = fixture::histogram(&strategy, 6, 5, &[1, 3]);
+
+        assert_eq!(split(true, true, &hist), (Some(vec![1, 3]), false));
+        // one category against the rest without the partition
+        assert_eq!(split(false, true, &hist).0.unwrap().len(), 1);
+    }
+}
@@ -1,22 +1,37 @@
 use std::cmp;
+use rayon::prelude::*;
 
 use crate::split_info::SplitInfoTrait;
 use crate::tree::Tree;
 use crate::strategy::Strategy;
 use crate::partition::Partition;
-use crate::config::{Float, TreeConfig};
-use crate::dmatrix::DMatrix;
+use crate::histogram::{Histogram, HistogramCache};
+use crate::config::{Int, Float, TreeConfig};
+use crate::dmatrix::{DMatrix, MISSING};
 use crate::random::Random;
+use crate::format::ModelError;
+use crate::flat::{FlatTree, FlatSource};
+use crate::control::SplitRecord;
 
+// nodes with at least this many rows search their features in parallel
+const PARALLEL_MIN_SIZE: usize = 10000;
+
+// (feature, node built from rows, parent and sibling derived by subtraction)
+type HistogramPlan = (usize, usize, Option<(usize, usize)>);
 
 #[derive(Clone)]
 pub struct TreeLearn<T: Tree + Clone + std::marker::Send, S: Strategy + Clone + std::marker::Send> {
     pub max_features: usize,
     pub max_depth: usize,
     pub partition: Partition,
+    pub cache: HistogramCache,
     pub tree: T,
+    // compiled from tree after fitting or loading, used for prediction
+    pub flat: FlatTree,
     pub strategy: S,
-    pub conf: TreeConfig
+    pub conf: TreeConfig,
+    // the splits taken while growing, recorded when set to Some before fit
+    pub split_log: Option<Vec<SplitRecord>>
 }
 
 impl<T: Tree + Clone + std::marker::Send, S: Strategy + Clone + std::marker::Send> TreeLearn<T, S> {
@@ -33,23 +48,27 @@ impl<T: Tree + Clone + std::marker::Send, S: Strategy + Clone + std::marker::Sen
             max_features,
             max_depth,
             partition,
+            cache: HistogramCache::new(),
             tree,
+            flat: FlatTree::default(),
             strategy,
-            conf
+            conf,
+            split_log: None
         };
 
         learn
     }
 
-    pub fn from_string(ss: &String) -> TreeLearn<T, S> {
-        let lines: Vec<&str> = ss.split("\n").collect();
-        let conf: TreeConfig = serde_json::from_str(lines.get(0).unwrap()).unwrap();
-        let ss = lines.get(1).unwrap().to_string();
-        let tree = T::from_string(& ss);
+    pub fn from_string(ss: &str) -> Result<TreeLearn<T, S>, ModelError> {
+        let (line_conf, line_tree) = ss.split_once('\n')
+            .ok_or(ModelError::Parse("tree is missing".to_string()))?;
+        let conf: TreeConfig = serde_json::from_str(line_conf)?;
+        let tree = T::from_string(line_tree)?;
         let mut learner: TreeLearn<T, S> = TreeLearn::new(& conf);
 
         learner.tree = tree;
-        learner
+        learner.compile();
+        Ok(learner)
     }
 
     pub fn to_string(& self) -> String {
@@ -71,28 +90,49 @@ impl<T: Tree + Clone + std::marker::Send, S: Strategy + Clone + std::marker::Sen
         self.partition = Partition::subsample(ns);
 
         self.grow_tree(0, m, conf, random);
+        self.cache = HistogramCache::new();
+        self.compile();
+    }
+
+    pub fn compile(& mut self) {
+        self.flat = FlatTree::compile(& self.tree);
     }
 
     pub fn grow_tree(& mut self, n: usize, m: &DMatrix, conf: &TreeConfig, random: &mut Random) {
         let split_info: S::T = self.find_best_split(n, m, random);
+        self.cache.searched(n);
+
         let mut split_info_t = T::T::new();
         split_info_t.set_node_id(split_info.get_node_id());
         split_info_t.set_feature_id(split_info.get_feature_id());
         split_info_t.set_treatment_id(split_info.get_treatment_id());
         split_info_t.set_iscat(split_info.get_iscat());
         split_info_t.set_value(split_info.get_value());
+        split_info_t.set_missing_left(split_info.get_missing_left());
         split_info_t.set_gain(split_info.get_gain());
         split_info_t.set_gain_importance(split_info.get_gain_importance());
         split_info_t.set_summary(split_info.get_summary());
         let split_info: T::T = split_info_t;
 
         self.tree.add_split(n, split_info.clone());
-        if split_info.get_value().is_none(){
+        if split_info.get_value().is_none() || self.tree.depth(n) >= self.max_depth {
+            self.cache.release(n);
             return ()
         }
 
-        if self.tree.depth(n) >= self.max_depth {
-            return ()
+        let depth = self.tree.depth(n);
+        if let Some(log) = self.split_log.as_mut() {
+            let record = SplitRecord {
+                node: n,
+                depth,
+                feature: m.name[split_info.get_feature_id()].clone(),
+                iscat: split_info.get_iscat(),
+                value: split_info.get_value().unwrap(),
+                missing_left: split_info.get_missing_left(),
+                gain: split_info.get_gain(),
+                size: self.partition.size[n]
+            };
+            log.push(record);
         }
 
         let left_index = self.tree.add_left(n);
@@ -100,6 +140,7 @@ impl<T: Tree + Clone + std::marker::Send, S: Strategy + Clone + std::marker::Sen
 
         let left_size = self.split(split_info.clone(), m);
         self.partition.split(split_info.get_node_id(), left_size);
+        self.cache.add_children(n, left_index, right_index);
 
         self.grow_tree(left_index, m, conf, random);
         self.grow_tree(right_index, m, conf, random);
@@ -111,13 +152,24 @@ impl<T: Tree + Clone + std::marker::Send, S: Strategy + Clone + std::marker::Sen
         let k = cmp::min(n_feature, self.max_features);
         let feature_ids = random.choose(n_feature, k, false);
 
-        let mut best_split: Option<S::T> = None;
+        // large nodes build and search the histograms of their features on the threads of the pool
+        let parallel = self.partition.size[n] >= PARALLEL_MIN_SIZE && rayon::current_num_threads() > 1;
+        self.build_histograms(n, &feature_ids, m, parallel);
 
-        for feature_id in feature_ids.iter() {
-            let indices = self.partition.get_indices(&n);
+        let strategy = & self.strategy;
+        let cache = & self.cache;
+        let search = |feature_id: &usize| {
+            strategy.find_best_split_hist(m, n, *feature_id, cache.get(n, *feature_id).unwrap())
+        };
 
-            let split_info = self.strategy.find_best_split(m, n, *feature_id, indices);
+        let splits: Vec<S::T> = match parallel {
+            true => feature_ids.par_iter().map(search).collect(),
+            false => feature_ids.iter().map(search).collect()
+        };
 
+        let mut best_split: Option<S::T> = None;
+
+        for split_info in splits {
             if best_split.is_none() {
                 best_split = Some(split_info);
                 continue;
@@ -140,6 +192,62 @@ impl<T: Tree + Clone + std::marker::Send, S: Strategy + Clone + std::marker::Sen
         best_split.unwrap()
     }
 
+    /*
+    the first child searched for a feature its parent has a histogram of builds the histogram
+    of the smaller sibling from rows and takes the larger one as the parent minus the smaller.
+    returns the node to build from rows and, when derived, the parent and the other sibling
+    */
+    fn plan_histogram(& self, n: usize, feature_id: usize) -> Option<(usize, Option<(usize, usize)>)> {
+        if self.cache.get(n, feature_id).is_some() {
+            return None;
+        }
+
+        match self.cache.derivable(n, feature_id) {
+            Some((parent, sibling)) if sibling > n => {
+                let (built, derived) = match self.partition.size[n] <= self.partition.size[sibling] {
+                    true => (n, sibling),
+                    false => (sibling, n)
+                };
+
+                Some((built, Some((parent, derived))))
+            },
+            _ => Some((n, None))
+        }
+    }
+
+    fn build_histograms(&mut self, n: usize, feature_ids: &[usize], m: &DMatrix, parallel: bool) {
+        let plans: Vec<HistogramPlan> = feature_ids.iter()
+            .filter_map(|f| self.plan_histogram(n, *f).map(|(built, derive)| (*f, built, derive)))
+            .collect();
+
+        let treatment_id = 0;
+        let n_treatment = *m.treatment_size.get(treatment_id).unwrap();
+        let n_channel = self.strategy.n_channel();
+        let mut hists: Vec<Histogram> = plans.iter().map(|(f, _, _)| {
+            let name = m.name.get(*f).unwrap();
+            let n_bin = m.bin_size.get(name).unwrap() + 1;
+            self.cache.acquire(n_channel, n_treatment, n_bin)
+        }).collect();
+
+        let partition = & self.partition;
+        let strategy = & self.strategy;
+        let fill = |(hist, (f, built, _)): (&mut Histogram, &HistogramPlan)| {
+            hist.fill(m, *f, treatment_id, partition.indices(*built), |y, w, value| strategy.row_stat(y, w, value));
+        };
+
+        match parallel {
+            true => hists.par_iter_mut().zip(plans.par_iter()).for_each(fill),
+            false => hists.iter_mut().zip(plans.iter()).for_each(fill)
+        }
+
+        for (hist, (f, built, derive)) in hists.into_iter().zip(plans.iter()) {
+            match derive {
+                Some((parent, derived)) => self.cache.insert_pair(*parent, *built, *derived, *f, hist),
+                None => self.cache.insert(*built, *f, hist)
+            }
+        }
+    }
+
     fn split(&mut self, split_info: T::T, m: &DMatrix) -> usize {
         let iscat = split_info.get_iscat();
         match iscat {
@@ -151,128 +259,47 @@ impl<T: Tree + Clone + std::marker::Send, S: Strategy + Clone + std::marker::Sen
     fn split_cont(&mut self, split_info: T::T, m: &DMatrix) -> usize {
         let n = split_info.get_node_id();
         let feature_id = split_info.get_feature_id();
-        let indices = self.partition.get_indices(&n);
-        m.sort_index(feature_id, indices);
+        let missing_left = split_info.get_missing_left();
 
         let split_value = split_info.get_value();
-        let threshold = split_value.as_ref().unwrap().get(0).unwrap();
-
-        let mut left_size = 0;
-        for i in 0..indices.len() {
-            let index = indices.get(i).unwrap();
-            let value = m.get(feature_id, *index);
+        let threshold = *split_value.as_ref().unwrap().first().unwrap();
 
-            if value.is_none() {
-                break;
-            }
-
-            if value.unwrap() > *threshold {
-                break;
-            }
-
-            left_size += 1;
-        }
-
-        left_size
+        let column = m.column(feature_id);
+        self.partition.divide(n, |i| match column[i] {
+            x if x == MISSING => missing_left,
+            x => x <= threshold
+        })
     }
 
     fn split_cat(&mut self, split_info: T::T, m: &DMatrix) -> usize {
         let n = split_info.get_node_id();
         let feature_id = split_info.get_feature_id();
-        let indices = self.partition.get_indices(&n);
+        let missing_left = split_info.get_missing_left();
 
         let split_value = split_info.get_value();
-        let threshold = split_value.as_ref().unwrap().get(0).unwrap();
-
-        let mut start = 0;
-        let mut end = indices.len() - 1;
-
-        while start < end {
-            let left_idx = indices.get(start).unwrap();
-            let left_value = m.get(feature_id, *left_idx);
+        let categories = split_value.as_ref().unwrap();
 
-            let right_idx = indices.get(end).unwrap();
-            let right_value = m.get(feature_id, *right_idx);
-
-            if (!left_value.is_none()) && (left_value.unwrap() == *threshold) {
-                start = start + 1;
-                continue;
-            }
-
-            if right_value.is_none() || (right_value.unwrap() != *threshold) {
-                end = end - 1;
-                continue;
-            }
-
-            let (x, y) = (indices[start], indices[end]);
-            indices[start] = y;
-            indices[end] = x;
-
-            start = start + 1;
-            end = end - 1;
-        }
-
-        start
+        let column = m.column(feature_id);
+        self.partition.divide(n, |i| match column[i] {
+            x if x == MISSING => missing_left,
+            x => categories.contains(&x)
+        })
     }
 
-    pub fn predict(& mut self, m: &DMatrix) -> Vec<Vec<Float>> {
-        let n = m.size();
-        let n_nodes = self.tree.size();
-        self.partition = Partition::refresh(n, n_nodes);
-        self.recursive_split(0, m);
-
-        let mut score: Vec<Vec<Float>> = Vec::new();
-        for _ in 0..n {
-            score.push(Vec::new());
-        }
-
-        for i in 0..n_nodes {
-            if !self.tree.is_leaf(i) {
-                continue;
-            }
-
-            let uplift = self.tree.get_uplift(i);
-            let indices = self.partition.get_indices(&i);
-
-            for k in indices.iter() {
-                for f in uplift.iter() {
-                    score[*k].push(f.clone());
-                }
-            }
-        }
-        score
+    pub fn predict(& self, m: &DMatrix) -> Vec<Vec<Float>> {
+        self.flat.predict(m)
     }
 
-    pub fn recursive_split(&mut self, n: usize, m: &DMatrix) {
-        let left_children = self.tree.get_left_children();
-        let left_index = left_children.get(n).unwrap().clone();
-
-        let right_children = self.tree.get_right_children();
-        let right_index = right_children.get(n).unwrap().clone();
-
-        if left_index.is_none() {
-            return ()
-        }
-
-        let split_info = self.tree.get_split_info(n).unwrap();
-        let left_size = self.split(split_info, m);
-
-        let left_index = left_index.clone().unwrap();
-        let right_index = right_index.clone().unwrap();
-
-        let start = self.partition.start.get(n).unwrap().clone();
-        let size = self.partition.size.get(n).unwrap().clone();
-        let right_size = size - left_size;
-
-        self.partition.set_node_range(left_index, start, left_size);
-        self.partition.set_node_range(right_index, start + left_size, right_size);
+    pub fn predict_row(& self, row: &[Option<Int>]) -> Vec<Float> {
+        self.flat.predict_row(row)
+    }
 
-        if left_size > 0 {
-            self.recursive_split(left_index, m);
-        }
+    pub fn predict_outcomes(& self, m: &DMatrix) -> Vec<Vec<Float>> {
+        (0..m.size()).map(|row| self.flat.leaf_outcome(self.flat.route(m, row))).collect()
+    }
 
-        if right_size > 0 {
-            self.recursive_split(right_index, m);
-        }
+    // id of the leaf node every row falls in
+    pub fn apply(& self, m: &DMatrix) -> Vec<usize> {
+        (0..m.size()).map(|row| self.flat.leaf_nodes[self.flat.route(m, row)]).collect()
     }
 }
@@ -7,11 +7,27 @@ pub mod bin;
 pub mod data_loader;
 pub mod split_info;
 pub mod strategy;
+pub mod histogram;
 pub mod statistic;
 pub mod kl;
 pub mod regression;
 pub mod partition;
 pub mod tree;
 pub mod learn;
+pub mod format;
+pub mod flat;
+pub mod control;
+pub mod metric;
+pub mod explain;
+pub mod shap;
+pub mod importance;
+pub mod pdp;
+pub mod rules;
+pub mod distill;
+pub mod render;
+pub mod sql;
 pub mod rf;
 pub mod python;
+
+#[cfg(test)]
+mod fixture;
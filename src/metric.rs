@@ -0,0 +1,233 @@
+use std::cmp::Ordering;
+use serde::{Serialize, Deserialize};
+
+use crate::config::Float;
+use crate::dmatrix::DMatrix;
+use crate::flat::{FlatTree, FlatSource};
+
+/*
+mean squared error of an uplift against the transformed outcome y (w - p) / (p (1 - p)),
+whose expectation given the features is the uplift. arm k is scored on the rows of the control
+and of arm k, with p the weighted share of arm k among them, and the error is averaged over
+the rows of all arms. rows with an empty score are left out, None when no row is left
+*/
+pub fn transformed_outcome_mse(m: &DMatrix, score: &[Vec<Float>]) -> Option<Float> {
+    let treatment = &m.treatments[0];
+    let n_arm = m.treatment_size[0];
+
+    let mut arm_weight: Vec<Float> = vec![0.0; n_arm];
+    for (i, s) in score.iter().enumerate() {
+        if !s.is_empty() {
+            arm_weight[treatment[i] as usize] += m.weights[i];
+        }
+    }
+
+    let mut total = 0.0;
+    let mut weight = 0.0;
+    for k in 1..n_arm {
+        let p = arm_weight[k] / (arm_weight[0] + arm_weight[k]);
+        if !(p > 0.0 && p < 1.0) {
+            continue;
+        }
+
+        for (i, s) in score.iter().enumerate() {
+            let t = treatment[i] as usize;
+            if s.is_empty() || (t != 0 && t != k) {
+                continue;
+            }
+
+            let w = match t {
+                0 => 0.0,
+                _ => 1.0
+            };
+            let y = m.response[i] * (w - p) / (p * (1.0 - p));
+            let e = y - s[k - 1];
+            total += m.weights[i] * e * e;
+            weight += m.weights[i];
+        }
+    }
+
+    match weight {
+        x if x > 0.0 => Some(total / weight),
+        _ => None
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UpliftMetric {
+    Qini,
+    Auuc,
+    TransformedOutcomeMse
+}
+
+impl UpliftMetric {
+
+    pub fn evaluate(& self, m: &DMatrix, score: &[Vec<Float>]) -> Option<Float> {
+        match self {
+            UpliftMetric::Qini => qini(m, score),
+            UpliftMetric::Auuc => auuc(m, score),
+            UpliftMetric::TransformedOutcomeMse => transformed_outcome_mse(m, score)
+        }
+    }
+
+    pub fn higher_is_better(& self) -> bool {
+        *self != UpliftMetric::TransformedOutcomeMse
+    }
+}
+
+/*
+area between the qini curve and the line of random targeting. rows are taken by decreasing
+score, rows of equal score together, so that ties are a straight line, and after each group
+the curve is y_t - y_c * n_t / n_c, sums of weighted response and weights of the treated and
+control rows. the area is divided by the total weight, and arms are scored against the control
+and averaged
+*/
+pub fn qini(m: &DMatrix, score: &[Vec<Float>]) -> Option<Float> {
+    uplift_curve_area(m, score, |y_t, y_c, n_t, n_c| match n_c {
+        x if x > 0.0 => y_t - y_c * n_t / n_c,
+        _ => y_t
+    }, true)
+}
+
+/*
+area under the uplift curve (y_t / n_t - y_c / n_c) * (n_t + n_c) divided by the total weight,
+with the rows taken as for qini
+*/
+pub fn auuc(m: &DMatrix, score: &[Vec<Float>]) -> Option<Float> {
+    uplift_curve_area(m, score, |y_t, y_c, n_t, n_c| match n_t > 0.0 && n_c > 0.0 {
+        true => (y_t / n_t - y_c / n_c) * (n_t + n_c),
+        false => 0.0
+    }, false)
+}
+
+fn uplift_curve_area<F>(m: &DMatrix, score: &[Vec<Float>], curve: F, baseline: bool) -> Option<Float>
+    where F: Fn(Float, Float, Float, Float) -> Float {
+
+    let treatment = &m.treatments[0];
+    let n_arm = m.treatment_size[0];
+    let mut areas: Vec<Float> = Vec::new();
+
+    for k in 1..n_arm {
+        let mut rows: Vec<usize> = (0..score.len())
+            .filter(|i| !score[*i].is_empty() && (treatment[*i] == 0 || treatment[*i] as usize == k))
+            .collect();
+        rows.sort_by(|a, b| score[*b][k - 1].partial_cmp(&score[*a][k - 1]).unwrap_or(Ordering::Equal));
+
+        let total: Float = rows.iter().map(|i| m.weights[*i]).sum();
+        if total <= 0.0 {
+            continue;
+        }
+
+        let (mut y_t, mut y_c, mut n_t, mut n_c) = (0.0, 0.0, 0.0, 0.0);
+        let mut area = 0.0;
+        let mut height = 0.0;
+        let mut start = 0;
+        while start < rows.len() {
+            let value = score[rows[start]][k - 1];
+            let mut end = start;
+            let mut width = 0.0;
+            while end < rows.len() && score[rows[end]][k - 1] == value {
+                let i = rows[end];
+                match treatment[i] {
+                    0 => {
+                        y_c += m.response[i] * m.weights[i];
+                        n_c += m.weights[i];
+                    },
+                    _ => {
+                        y_t += m.response[i] * m.weights[i];
+                        n_t += m.weights[i];
+                    }
+                }
+                width += m.weights[i];
+                end += 1;
+            }
+
+            let next = curve(y_t, y_c, n_t, n_c);
+            area += width * (height + next) / 2.0 / total;
+            height = next;
+            start = end;
+        }
+
+        let area = match baseline {
+            true => (area - height / 2.0) / total,
+            false => area / total
+        };
+        areas.push(area);
+    }
+
+    match areas.len() {
+        0 => None,
+        n => Some(areas.iter().sum::<Float>() / n as Float)
+    }
+}
+
+/*
+weighted R² of score against target for every treatment, how much of the variance of the
+target score explains. 1 when they agree, and NaN when the target is constant
+*/
+pub fn r_squared(target: &[Vec<Float>], score: &[Vec<Float>], weights: &[Float]) -> Vec<Float> {
+    let n_uplift = target.first().map(|x| x.len()).unwrap_or(0);
+    let total: f64 = weights.iter().map(|x| *x as f64).sum();
+
+    (0..n_uplift).map(|k| {
+        let mean = target.iter().zip(weights.iter()).map(|(t, w)| t[k] as f64 * *w as f64).sum::<f64>() / total;
+        let mut residual = 0.0;
+        let mut variance = 0.0;
+        for ((t, s), w) in target.iter().zip(score.iter()).zip(weights.iter()) {
+            let w = *w as f64;
+            residual += w * (t[k] as f64 - s[k] as f64).powi(2);
+            variance += w * (t[k] as f64 - mean).powi(2);
+        }
+
+        (1.0 - residual / variance) as Float
+    }).collect()
+}
+
+/*
+running out of bag uplift of a forest: each row is scored by the trees whose sample missed it.
+with empty samples every row is scored by every tree, which is the running average of the forest
+*/
+pub struct OutOfBag {
+    sum: Vec<Vec<Float>>,
+    count: Vec<usize>,
+    in_bag: Vec<bool>,
+}
+
+impl OutOfBag {
+
+    pub fn new(n: usize) -> OutOfBag {
+        OutOfBag { sum: vec![Vec::new(); n], count: vec![0; n], in_bag: vec![false; n] }
+    }
+
+    // sample holds the rows the tree was grown on, repeated rows included
+    pub fn add(&mut self, m: &DMatrix, tree: &FlatTree, sample: &[usize]) {
+        self.in_bag.iter_mut().for_each(|x| *x = false);
+        for i in sample.iter() {
+            self.in_bag[*i] = true;
+        }
+
+        for i in 0..self.in_bag.len() {
+            if self.in_bag[i] {
+                continue;
+            }
+
+            let uplift = tree.leaf_uplift(tree.route(m, i));
+            self.sum[i] = match self.sum[i].len() {
+                0 => uplift,
+                _ => uplift.iter().zip(self.sum[i].iter()).map(|(a, b)| a + b).collect()
+            };
+            self.count[i] += 1;
+        }
+    }
+
+    pub fn score(& self) -> Vec<Vec<Float>> {
+        self.sum.iter().zip(self.count.iter()).map(|(s, c)| {
+            s.iter().map(|x| x / *c as Float).collect()
+        }).collect()
+    }
+
+    pub fn mse(& self, m: &DMatrix) -> Option<Float> {
+        transformed_outcome_mse(m, &self.score())
+    }
+}
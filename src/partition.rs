@@ -6,6 +6,7 @@ pub struct Partition {
     pub data_ids: Vec<usize>,
     pub start: Vec<usize>,
     pub size: Vec<usize>,
+    buffer: Vec<usize>,
 }
 
 impl Partition {
@@ -23,7 +24,8 @@ impl Partition {
         Partition {
             data_ids,
             start,
-            size
+            size,
+            buffer: Vec::new()
         }
     }
 
@@ -34,6 +36,35 @@ impl Partition {
         &mut self.data_ids[*start..end]
     }
 
+    /*
+    moves the rows of a node for which is_left holds to the front, keeping the order on both sides,
+    and returns how many there are
+    */
+    pub fn divide<F: Fn(usize) -> bool>(&mut self, node: usize, is_left: F) -> usize {
+        let start = self.start[node];
+        let end = start + self.size[node];
+
+        self.buffer.clear();
+        let mut left_size = 0;
+        for i in start..end {
+            let index = self.data_ids[i];
+            if is_left(index) {
+                self.data_ids[start + left_size] = index;
+                left_size += 1;
+            } else {
+                self.buffer.push(index);
+            }
+        }
+
+        self.data_ids[start + left_size..end].copy_from_slice(&self.buffer);
+        left_size
+    }
+
+    pub fn indices(& self, node_id: usize) -> &[usize] {
+        let start = self.start[node_id];
+        &self.data_ids[start..start + self.size[node_id]]
+    }
+
     pub fn split(&mut self, node: usize, left_size: usize) {
         let start = self.start.get(node).unwrap().clone();
         let size = self.size.get(node).unwrap().clone();
@@ -57,7 +88,8 @@ impl Partition {
         Partition {
             data_ids,
             start,
-            size
+            size,
+            buffer: Vec::new()
         }
     }
 
@@ -0,0 +1,150 @@
+use std::cmp;
+use std::ops::Range;
+use rayon::prelude::*;
+use serde::Serialize;
+
+use crate::config::{Int, Float};
+use crate::data_loader::DataLoader;
+use crate::dmatrix::DMatrix;
+use crate::flat::{FlatTree, FlatSource};
+use crate::explain::{Condition, n_bins, decode_bins};
+
+/*
+a bin of a feature and the original values that fall in it. None is the missing value, which
+has a route of its own in every split
+*/
+#[derive(Clone, Debug, Serialize)]
+pub struct GridPoint {
+    pub bin: Option<Int>,
+    pub condition: Condition,
+}
+
+/*
+partial dependence of the uplift on some features: the features are set to every combination of
+their grid points in all the rows, the last feature varying fastest, and the uplift is averaged
+over the rows by weight. individual holds the curve of every row, [row][point][arm], when asked
+*/
+#[derive(Clone, Debug, Serialize)]
+pub struct PartialDependence {
+    pub features: Vec<String>,
+    pub grid: Vec<Vec<GridPoint>>,
+    pub average: Vec<Vec<Float>>,
+    pub individual: Option<Vec<Vec<Vec<Float>>>>,
+}
+
+/*
+every bin of a fitted feature, then missing. the bin of a continuous x is the number of
+thresholds <= x, and the bins of a discrete feature are the values of its mapping, the last one
+holding the values not seen in training
+*/
+pub fn grid(loader: &DataLoader, feature: &str) -> Vec<GridPoint> {
+    let mut points: Vec<GridPoint> = (0..n_bins(loader, feature))
+        .map(|b| GridPoint { bin: Some(b as Int), condition: decode_bins(loader, feature, &[b]) })
+        .collect();
+
+    points.push(GridPoint { bin: None, condition: Condition::Missing });
+    points
+}
+
+// the bins of every combination of grid points, the last feature varying fastest
+fn combinations(grid: &[Vec<GridPoint>]) -> Vec<Vec<Option<Int>>> {
+    let mut combos: Vec<Vec<Option<Int>>> = vec![Vec::new()];
+    for points in grid.iter() {
+        combos = combos.iter()
+            .flat_map(|c| points.iter().map(move |p| {
+                let mut c = c.clone();
+                c.push(p.bin);
+                c
+            }))
+            .collect();
+    }
+
+    combos
+}
+
+// [row][point][arm] for the rows, averaged over the trees
+fn curves(trees: &[&FlatTree], m: &DMatrix, features: &[usize], combos: &[Vec<Option<Int>>], rows: Range<usize>)
+    -> Vec<Vec<Vec<Float>>> {
+
+    let n_tree = trees.len() as Float;
+    rows.map(|row| {
+        combos.iter().map(|combo| {
+            let mut sum: Vec<Float> = Vec::new();
+            for tree in trees.iter() {
+                let leaf = tree.descend(|f| match features.iter().position(|x| *x == f) {
+                    Some(k) => combo[k],
+                    None => m.get(f, row)
+                });
+                let value = tree.leaf_uplift(leaf);
+                sum = match sum.len() {
+                    0 => value,
+                    _ => value.iter().zip(sum.iter()).map(|(a, b)| a + b).collect()
+                };
+            }
+
+            sum.iter().map(|x| x / n_tree).collect()
+        }).collect()
+    }).collect()
+}
+
+/*
+partial dependence of the uplift on the features of m, by their index, with the curves of the
+rows when individual. the rows run in chunks in parallel on the current pool
+*/
+pub fn partial_dependence(trees: &[&FlatTree], loader: &DataLoader, m: &DMatrix, features: &[usize],
+    individual: bool, chunk_size: usize) -> PartialDependence {
+
+    let names: Vec<String> = features.iter().map(|f| m.name[*f].clone()).collect();
+    let grid: Vec<Vec<GridPoint>> = names.iter().map(|x| grid(loader, x)).collect();
+    let combos = combinations(&grid);
+
+    let n = m.size();
+    let chunks: Vec<Range<usize>> = (0..n).step_by(chunk_size)
+        .map(|x| x..cmp::min(x + chunk_size, n))
+        .collect();
+
+    // weighted sums of every chunk, and its curves when they are kept
+    let results: Vec<_> = chunks.into_par_iter().map(|rows| {
+        let start = rows.start;
+        let values = curves(trees, m, features, &combos, rows);
+        let mut sum: Vec<Vec<Float>> = Vec::new();
+        for (i, row) in values.iter().enumerate() {
+            let w = m.weights[start + i];
+            sum = match sum.len() {
+                0 => row.iter().map(|p| p.iter().map(|x| x * w).collect()).collect(),
+                _ => row.iter().zip(sum.iter()).map(|(p, s)| p.iter().zip(s.iter()).map(|(x, y)| x * w + y).collect()).collect()
+            };
+        }
+
+        match individual {
+            true => (sum, values),
+            false => (sum, Vec::new())
+        }
+    }).collect();
+
+    let total: Float = m.weights.iter().sum();
+    let mut average: Vec<Vec<Float>> = Vec::new();
+    let mut curves_all: Vec<Vec<Vec<Float>>> = Vec::new();
+    for (sum, values) in results.into_iter() {
+        average = match average.len() {
+            0 => sum,
+            _ => sum.iter().zip(average.iter()).map(|(p, s)| p.iter().zip(s.iter()).map(|(x, y)| x + y).collect()).collect()
+        };
+        curves_all.extend(values);
+    }
+    for point in average.iter_mut() {
+        for x in point.iter_mut() {
+            *x /= total;
+        }
+    }
+
+    PartialDependence {
+        features: names,
+        grid,
+        average,
+        individual: match individual {
+            true => Some(curves_all),
+            false => None
+        }
+    }
+}
@@ -1,32 +1,68 @@
+use std::collections::BTreeMap;
+use std::thread;
+use std::sync::Mutex;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::Duration;
 use pyo3::prelude::*;
-use pyo3::types::PyDict;
+use pyo3::types::{PyDict, PyList};
+use pyo3::exceptions::{PyIOError, PyValueError, PyTypeError, PyKeyboardInterrupt};
+use serde::Serialize;
 
 use crate::rf::RandomForest;
-use crate::config::{Float, TreeConfig};
+use crate::config::{Int, Float, TreeConfig};
 use crate::tree::{Tree, ClassificationTree, RegressionTree};
 use crate::strategy::Strategy;
 use crate::kl::KLStrategy;
 use crate::regression::RegressionStrategy;
 use crate::dmatrix::DMatrix;
 use crate::data_loader::DataLoader;
+use crate::format::ModelError;
+use crate::flat::MappedForest;
+use crate::explain::Explanation;
+use crate::metric::UpliftMetric;
+use crate::importance::Importance;
+use crate::pdp::PartialDependence;
+use crate::rules::{Rule, RuleOptions, to_csv, to_json};
+use crate::control::{Control, Cancelled, FitMonitor, TreeInfo, Action, EarlyStopping};
+use crate::bin::{EncodeMethod, BinStrategy};
 
 trait CausalModelInterface {
     fn get_loader(&mut self) -> &mut DataLoader;
     fn to_string(& self) -> String;
     fn save(& self, fname: &String) -> std::io::Result<()>;
-    fn fit(&mut self, m: DMatrix);
-    fn predict(&mut self, m: DMatrix) -> Vec<Vec<Float>>;
+    fn save_binary(& self, fname: &String) -> std::io::Result<()>;
+    fn fit(&mut self, m: DMatrix, control: &Control, monitor: &mut FitMonitor) -> Result<(), Cancelled>;
+    fn fit_more(&mut self, m: DMatrix, n_tree: usize, control: &Control, monitor: &mut FitMonitor) -> Result<(), Cancelled>;
+    fn predict(& self, m: DMatrix, control: &Control) -> Result<Vec<Vec<Float>>, Cancelled>;
+    fn predict_staged(& self, m: DMatrix) -> Vec<Vec<Vec<Float>>>;
+    fn predict_per_tree(& self, m: DMatrix) -> Vec<Vec<Vec<Float>>>;
+    fn predict_outcomes(& self, m: DMatrix) -> Vec<Vec<Float>>;
+    fn apply(& self, m: DMatrix) -> Vec<Vec<usize>>;
+    fn explain(& self, m: &DMatrix, row: usize) -> Explanation;
+    fn shap_values(& self, m: DMatrix) -> Vec<Vec<Vec<Float>>>;
+    fn expected_value(& self) -> Vec<Float>;
+    fn permutation_importance(& self, m: &DMatrix, metric: UpliftMetric, n_repeat: usize) -> Vec<Importance>;
+    fn partial_dependence(& self, m: &DMatrix, features: &[usize], individual: bool) -> PartialDependence;
+    fn rules(& self, options: &RuleOptions) -> Vec<Rule>;
+    fn distill(& self, m: &DMatrix, max_depth: usize, min_samples_leaf: usize) -> (Box<dyn CausalModelInterface + Send>, Vec<Float>);
+    fn tree_to_text(& self, index: usize) -> Option<String>;
+    fn tree_to_dot(& self, index: usize) -> Option<String>;
+    fn to_sql(& self, table: &str) -> String;
+    fn forest_weights(& self, train: &DMatrix, query: &DMatrix) -> Vec<Vec<(usize, Float)>>;
 }
 
 trait CausalModelFactory {
     fn new() -> Self;
-    fn create<T: Tree + Clone + std::marker::Send + 'static, S: Strategy + Clone + std::marker::Send + 'static>(& self, config: &PyDict) -> Box<dyn CausalModelInterface + Send>;
-    fn from_string<T: Tree + Clone + std::marker::Send + 'static, S: Strategy + Clone + std::marker::Send + 'static>(& self, ss: &String) -> Box<dyn CausalModelInterface + Send>;
-    fn load<T: Tree + Clone + std::marker::Send + 'static, S: Strategy + Clone + std::marker::Send + 'static>(& self, fname: &String) -> Box<dyn CausalModelInterface + Send>;
+    fn create<T: Tree + Clone + std::marker::Send + 'static, S: Strategy + Clone + std::marker::Send + 'static>(& self, config: &PyDict) -> PyResult<Box<dyn CausalModelInterface + Send>>;
+    fn from_string<T: Tree + Clone + std::marker::Send + 'static, S: Strategy + Clone + std::marker::Send + 'static>(& self, ss: &String) -> Result<Box<dyn CausalModelInterface + Send>, ModelError>;
+    fn load<T: Tree + Clone + std::marker::Send + 'static, S: Strategy + Clone + std::marker::Send + 'static>(& self, fname: &String) -> Result<Box<dyn CausalModelInterface + Send>, ModelError>;
 }
 
 pub struct RandomForestFactory {}
 
+// how often python signals are checked while rust work runs without the GIL
+const SIGNAL_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
 
 pub fn extract_vec(conf: &PyDict, key: &str) -> Vec<String> {
     match conf.get_item(key) {
@@ -98,6 +134,148 @@ pub fn extract_string(conf: &PyDict, key: &str, val: String) -> String {
     }
 }
 
+// a strategy is either a name, or a list of thresholds for user-supplied bins
+pub fn extract_bin_strategy(item: &PyAny) -> PyResult<BinStrategy> {
+    if let Ok(threshold) = item.extract::<Vec<f64>>() {
+        return Ok(BinStrategy::Manual(threshold.iter().map(|&x| x as Float).collect()));
+    }
+
+    match item.extract::<String>() {
+        Ok(name) => match name.as_str() {
+            "quantile" => Ok(BinStrategy::Quantile),
+            "equal_width" => Ok(BinStrategy::EqualWidth),
+            "sketch" => Ok(BinStrategy::Sketch),
+            x => Err(PyValueError::new_err(format!("bin_strategy {} not found", x)))
+        },
+        Err(_) => Err(PyValueError::new_err("bin_strategy should be a name or a list of thresholds"))
+    }
+}
+
+pub fn extract_bin_strategy_map(conf: &PyDict, key: &str) -> PyResult<BTreeMap<String, BinStrategy>> {
+    let mut map = BTreeMap::new();
+    if let Ok(Some(item)) = conf.get_item(key) {
+        if let Ok(dict) = item.downcast::<PyDict>() {
+            for (k, v) in dict.iter() {
+                if let Ok(name) = k.extract::<String>() {
+                    map.insert(name, extract_bin_strategy(v)?);
+                }
+            }
+        }
+    }
+
+    Ok(map)
+}
+
+// a keyword argument of a method, default when it is not given
+pub fn extract_kwarg<'py, T: FromPyObject<'py>>(kwargs: Option<&Bound<'py, PyDict>>, key: &str, val: T) -> PyResult<T> {
+    match kwargs.map(|x| x.get_item(key)).transpose()?.flatten() {
+        Some(x) => x.extract(),
+        None => Ok(val)
+    }
+}
+
+// raises the TypeError of python for a keyword argument the method does not take
+pub fn check_kwargs(kwargs: Option<&Bound<'_, PyDict>>, method: &str, keys: &[&str]) -> PyResult<()> {
+    for key in kwargs.iter().flat_map(|x| x.keys()) {
+        let key: String = key.extract()?;
+        if !keys.contains(&key.as_str()) {
+            return Err(PyTypeError::new_err(format!("{}() got an unexpected keyword argument '{}'", method, key)));
+        }
+    }
+    Ok(())
+}
+
+// the keyword arguments of rules and export_rules
+pub fn extract_rule_options(kwargs: Option<&Bound<'_, PyDict>>, method: &str) -> PyResult<RuleOptions> {
+    check_kwargs(kwargs, method, &["min_support", "tolerance", "max_rules", "arm"])?;
+    Ok(RuleOptions {
+        min_support: extract_kwarg(kwargs, "min_support", 0.0)?,
+        tolerance: extract_kwarg(kwargs, "tolerance", None)?,
+        max_rules: extract_kwarg(kwargs, "max_rules", None)?,
+        arm: extract_kwarg(kwargs, "arm", 0)?
+    })
+}
+
+pub fn to_py_err(e: ModelError) -> PyErr {
+    match e {
+        ModelError::Io(x) => PyIOError::new_err(x.to_string()),
+        x => PyValueError::new_err(x.to_string())
+    }
+}
+
+/*
+runs f on a worker thread with the GIL released, while the calling thread keeps checking for
+python signals. a pending signal such as Ctrl-C cancels the control, and is raised once f stops
+*/
+pub fn run_interruptible<R, F>(py: Python, control: &Control, f: F) -> PyResult<R>
+    where R: Send, F: FnOnce() -> Result<R, Cancelled> + Send {
+
+    let (result, signal) = py.allow_threads(|| {
+        thread::scope(|scope| {
+            let (tx, rx) = mpsc::channel::<()>();
+            let handle = scope.spawn(move || {
+                let result = f();
+                tx.send(()).ok();
+                result
+            });
+
+            let mut signal: Option<PyErr> = None;
+            while let Err(RecvTimeoutError::Timeout) = rx.recv_timeout(SIGNAL_CHECK_INTERVAL) {
+                if signal.is_some() {
+                    continue;
+                }
+
+                if let Err(e) = Python::with_gil(|py| py.check_signals()) {
+                    control.cancel();
+                    signal = Some(e);
+                }
+            }
+
+            (handle.join().unwrap(), signal)
+        })
+    });
+
+    // a signal that arrives after the work has finished is dropped, so an error always means the work was undone
+    match (result, signal) {
+        (Ok(x), _) => Ok(x),
+        (Err(_), Some(e)) => Err(e),
+        (Err(_), None) => Err(PyKeyboardInterrupt::new_err("cancelled"))
+    }
+}
+
+// python objects from anything serializable, through the json module
+fn to_py_json<S: Serialize>(py: Python, value: &S) -> PyResult<PyObject> {
+    let ss = serde_json::to_string(value).map_err(|e| PyValueError::new_err(e.to_string()))?;
+    let value = py.import_bound("json")?.call_method1("loads", (ss,))?;
+    Ok(value.unbind())
+}
+
+fn tree_info_to_dict<'py>(py: Python<'py>, info: &TreeInfo) -> PyResult<Bound<'py, PyDict>> {
+    let splits = PyList::empty_bound(py);
+    for split in info.splits.iter() {
+        let dict = PyDict::new_bound(py);
+        dict.set_item("node", split.node)?;
+        dict.set_item("depth", split.depth)?;
+        dict.set_item("feature", &split.feature)?;
+        dict.set_item("iscat", split.iscat)?;
+        dict.set_item("value", &split.value)?;
+        dict.set_item("missing_left", split.missing_left)?;
+        dict.set_item("gain", split.gain)?;
+        dict.set_item("size", split.size)?;
+        splits.append(dict)?;
+    }
+
+    let dict = PyDict::new_bound(py);
+    dict.set_item("index", info.index)?;
+    dict.set_item("depth", info.depth)?;
+    dict.set_item("n_leaves", info.n_leaves)?;
+    dict.set_item("elapsed", info.elapsed.as_secs_f64())?;
+    dict.set_item("oob", info.oob)?;
+    dict.set_item("validation", info.validation)?;
+    dict.set_item("splits", splits)?;
+    Ok(dict)
+}
+
 impl CausalModelFactory for RandomForestFactory {
 
     fn new() -> Self {
@@ -105,7 +283,7 @@ impl CausalModelFactory for RandomForestFactory {
         factory
     }
 
-    fn create<T: Tree + Clone + std::marker::Send + 'static, S: Strategy + Clone + std::marker::Send + 'static>(& self, conf: &PyDict) -> Box<dyn CausalModelInterface + Send> {
+    fn create<T: Tree + Clone + std::marker::Send + 'static, S: Strategy + Clone + std::marker::Send + 'static>(& self, conf: &PyDict) -> PyResult<Box<dyn CausalModelInterface + Send>> {
         let index_cols = extract_vec(conf, "index");
         let feature_cols = extract_vec(conf, "feature");
         let cat_cols = extract_vec(conf, "cat");
@@ -123,6 +301,21 @@ impl CausalModelFactory for RandomForestFactory {
         let n_tree = extract_usize(conf, "n_tree", 100);
         let subsample = extract_float(conf, "subsample", 1.0);
         let n_thread = extract_usize(conf, "n_thread", 1);
+        let cat_partition = extract_bool(conf, "cat_partition", false);
+        let encode_cols = extract_vec(conf, "encode");
+        let encode_method = match extract_string(conf, "encode_method", "uplift".to_string()).as_str() {
+            "uplift" => EncodeMethod::Uplift,
+            "hash" => EncodeMethod::Hash,
+            x => return Err(PyValueError::new_err(format!("encode_method {} not found", x)))
+        };
+        let encode_n_fold = extract_usize(conf, "encode_n_fold", 5);
+        let encode_smoothing = extract_float(conf, "encode_smoothing", 10.0);
+        let n_bucket = extract_usize(conf, "n_bucket", 64);
+        let bin_strategy = match conf.get_item("bin_strategy") {
+            Ok(Some(item)) => extract_bin_strategy(item)?,
+            _ => BinStrategy::Quantile
+        };
+        let feature_bin_strategy = extract_bin_strategy_map(conf, "feature_bin_strategy")?;
 
         let seed = extract_usize(conf, "seed", usize::MAX);
         let seed = match seed {
@@ -133,25 +326,28 @@ impl CausalModelFactory for RandomForestFactory {
         let conf = TreeConfig {
             index_cols, feature_cols, cat_cols, treatment_cols, y_col, weight_col,
             n_bin, min_samples_leaf, min_samples_treatment, n_reg, alpha, normalization,
-            max_features, max_depth, n_tree, subsample, n_thread, seed
+            max_features, max_depth, n_tree, subsample, n_thread, seed, cat_partition,
+            int_bits: Int::BITS, encode_cols, encode_method, encode_n_fold, encode_smoothing, n_bucket,
+            bin_strategy, feature_bin_strategy, n_tree_configured: None
         };
+        conf.validate().map_err(PyValueError::new_err)?;
 
         let model: RandomForest<T, S> = RandomForest::new(conf);
 
         let interface: RandomForestInterface<T, S> = RandomForestInterface { model };
-        Box::new(interface) as Box<dyn CausalModelInterface + Send>
+        Ok(Box::new(interface) as Box<dyn CausalModelInterface + Send>)
     }
 
-    fn from_string<T: Tree + Clone + std::marker::Send + 'static, S: Strategy + Clone + std::marker::Send + 'static>(& self, ss: &String) -> Box<dyn CausalModelInterface + Send> {
-        let model: RandomForest<T, S> = RandomForest::from_string(ss);
+    fn from_string<T: Tree + Clone + std::marker::Send + 'static, S: Strategy + Clone + std::marker::Send + 'static>(& self, ss: &String) -> Result<Box<dyn CausalModelInterface + Send>, ModelError> {
+        let model: RandomForest<T, S> = RandomForest::from_string(ss)?;
         let interface: RandomForestInterface<T, S> = RandomForestInterface { model };
-        Box::new(interface) as Box<dyn CausalModelInterface + Send>
+        Ok(Box::new(interface) as Box<dyn CausalModelInterface + Send>)
     }
 
-    fn load<T: Tree + Clone + std::marker::Send + 'static, S: Strategy + Clone + std::marker::Send + 'static>(& self, fname: &String) -> Box<dyn CausalModelInterface + Send> {
-        let model: RandomForest<T, S> = RandomForest::load(fname).unwrap();
+    fn load<T: Tree + Clone + std::marker::Send + 'static, S: Strategy + Clone + std::marker::Send + 'static>(& self, fname: &String) -> Result<Box<dyn CausalModelInterface + Send>, ModelError> {
+        let model: RandomForest<T, S> = RandomForest::load(fname)?;
         let interface: RandomForestInterface<T, S> = RandomForestInterface { model };
-        Box::new(interface) as Box<dyn CausalModelInterface + Send>
+        Ok(Box::new(interface) as Box<dyn CausalModelInterface + Send>)
     }
 }
 
@@ -174,12 +370,82 @@ impl<T: Tree + Clone + std::marker::Send + 'static, S: Strategy + Clone + std::m
         self.model.save(fname)
     }
 
-    fn fit(&mut self, m: DMatrix) {
-        self.model.fit(m);
+    fn save_binary(& self, fname: &String) -> std::io::Result<()> {
+        self.model.save_binary(fname)
+    }
+
+    fn fit(&mut self, m: DMatrix, control: &Control, monitor: &mut FitMonitor) -> Result<(), Cancelled> {
+        self.model.fit_monitored(m, control, monitor)
+    }
+
+    fn fit_more(&mut self, m: DMatrix, n_tree: usize, control: &Control, monitor: &mut FitMonitor) -> Result<(), Cancelled> {
+        self.model.fit_more(m, n_tree, control, monitor)
+    }
+
+    fn predict(& self, m: DMatrix, control: &Control) -> Result<Vec<Vec<Float>>, Cancelled> {
+        self.model.predict_with(m, control)
+    }
+
+    fn predict_staged(& self, m: DMatrix) -> Vec<Vec<Vec<Float>>> {
+        self.model.predict_staged(m)
+    }
+
+    fn predict_per_tree(& self, m: DMatrix) -> Vec<Vec<Vec<Float>>> {
+        self.model.predict_per_tree(m)
+    }
+
+    fn predict_outcomes(& self, m: DMatrix) -> Vec<Vec<Float>> {
+        self.model.predict_outcomes(m)
+    }
+
+    fn apply(& self, m: DMatrix) -> Vec<Vec<usize>> {
+        self.model.apply(m)
+    }
+
+    fn explain(& self, m: &DMatrix, row: usize) -> Explanation {
+        self.model.explain(m, row)
+    }
+
+    fn shap_values(& self, m: DMatrix) -> Vec<Vec<Vec<Float>>> {
+        self.model.shap_values(m)
+    }
+
+    fn expected_value(& self) -> Vec<Float> {
+        self.model.expected_value()
+    }
+
+    fn permutation_importance(& self, m: &DMatrix, metric: UpliftMetric, n_repeat: usize) -> Vec<Importance> {
+        self.model.permutation_importance(m, metric, n_repeat)
+    }
+
+    fn partial_dependence(& self, m: &DMatrix, features: &[usize], individual: bool) -> PartialDependence {
+        self.model.partial_dependence(m, features, individual)
     }
 
-    fn predict(&mut self, m: DMatrix) -> Vec<Vec<Float>> {
-        self.model.predict(m)
+    fn rules(& self, options: &RuleOptions) -> Vec<Rule> {
+        self.model.rules(options)
+    }
+
+    fn distill(& self, m: &DMatrix, max_depth: usize, min_samples_leaf: usize) -> (Box<dyn CausalModelInterface + Send>, Vec<Float>) {
+        let (model, in_sample) = self.model.distill(m, max_depth, min_samples_leaf);
+        let interface: RandomForestInterface<RegressionTree, RegressionStrategy> = RandomForestInterface { model };
+        (Box::new(interface) as Box<dyn CausalModelInterface + Send>, in_sample)
+    }
+
+    fn tree_to_text(& self, index: usize) -> Option<String> {
+        self.model.tree_to_text(index)
+    }
+
+    fn tree_to_dot(& self, index: usize) -> Option<String> {
+        self.model.tree_to_dot(index)
+    }
+
+    fn to_sql(& self, table: &str) -> String {
+        self.model.to_sql(table)
+    }
+
+    fn forest_weights(& self, train: &DMatrix, query: &DMatrix) -> Vec<Vec<(usize, Float)>> {
+        self.model.forest_weights(train, query)
     }
 }
 
@@ -192,27 +458,27 @@ pub struct CausalModel {
 impl CausalModel {
 
     #[new]
-    pub fn new(name: String, conf: &PyDict) -> CausalModel {
+    pub fn new(name: String, conf: &PyDict) -> PyResult<CausalModel> {
         let name_str = name.as_str();
         let ptr = match name_str {
             "RandomForestClassifier" => {
                 let factory = RandomForestFactory::new();
-                let interface = factory.create::<ClassificationTree, KLStrategy>(conf);
+                let interface = factory.create::<ClassificationTree, KLStrategy>(conf)?;
                 Some(interface)
             },
             "RandomForestRegressor" => {
                 let factory = RandomForestFactory::new();
-                let interface = factory.create::<RegressionTree, RegressionStrategy>(conf);
+                let interface = factory.create::<RegressionTree, RegressionStrategy>(conf)?;
                 Some(interface)
             },
-            &_ => {panic!("model name {} not found", name);}
+            &_ => return Err(PyValueError::new_err(format!("model name {} not found", name)))
         };
 
         let ptr = ptr.unwrap();
-        CausalModel { ptr }
+        Ok(CausalModel { ptr })
     }
 
-    pub fn load(&self, name: String, path: String) -> CausalModel {
+    pub fn load(&self, name: String, path: String) -> PyResult<CausalModel> {
         let name_str = name.as_str();
         let ptr = match name_str {
             "RandomForestClassifier" => {
@@ -225,34 +491,406 @@ impl CausalModel {
                 let interface = factory.load::<RegressionTree, RegressionStrategy>(&path);
                 Some(interface)
             },
-            &_ => {panic!("model name {} not found", name);}
+            &_ => return Err(PyValueError::new_err(format!("model name {} not found", name)))
         };
-        let ptr = ptr.unwrap();
-        CausalModel { ptr }
+        let ptr = ptr.unwrap().map_err(to_py_err)?;
+        Ok(CausalModel { ptr })
     }
 
     pub fn save(&self, path: String) -> () {
         (*self.ptr).save(&path).unwrap()
     }
 
-    pub fn fit(& mut self, headers: Vec<String>, arr: Vec<Vec<Option<Float>>>) -> () {
+    pub fn save_binary(&self, path: String) -> () {
+        (*self.ptr).save_binary(&path).unwrap()
+    }
+
+    /*
+    callback(info) is called after each tree with a dict of index, depth, n_leaves, elapsed seconds,
+    oob (None unless oob is set), validation (None without valid) and splits (empty unless
+    log_splits is set). returning False keeps the trees built so far and stops, and so does a
+    metric that has not improved by min_delta for patience trees. valid is scored with the same
+    headers. on KeyboardInterrupt, or when the callback raises, the model is left as it was.
+    the options are keyword arguments: callback=None, oob=False, log_splits=False, valid=None,
+    patience=None and min_delta=0.0
+    */
+    #[pyo3(signature = (headers, arr, **options))]
+    pub fn fit(& mut self, py: Python, headers: Vec<String>, arr: Vec<Vec<Option<Float>>>,
+        options: Option<&Bound<'_, PyDict>>) -> PyResult<()> {
+
+        let options = FitOptions::extract(options, "fit")?;
+        self.run_fit(py, headers, arr, None, options)
+    }
+
+    // warm start: adds up to n_tree trees with the bins of the model, with the options of fit
+    #[pyo3(signature = (headers, arr, n_tree, **options))]
+    pub fn fit_more(& mut self, py: Python, headers: Vec<String>, arr: Vec<Vec<Option<Float>>>, n_tree: usize,
+        options: Option<&Bound<'_, PyDict>>) -> PyResult<()> {
+
+        let options = FitOptions::extract(options, "fit_more")?;
+        self.run_fit(py, headers, arr, Some(n_tree), options)
+    }
+
+    pub fn predict(& mut self, py: Python, headers: Vec<String>, arr: Vec<Vec<Option<Float>>>) -> PyResult<Vec<Vec<Float>>> {
+        self.validate(&headers)?;
+        let indices: Vec<Vec<String>> = Vec::new();
+        let control = Control::new();
+
+        let ptr = &mut self.ptr;
+        run_interruptible(py, &control, || {
+            let loader = (*ptr).get_loader();
+            let m = loader.from_memory(&headers, &indices, &arr);
+            (*ptr).predict(m, &control)
+        })
+    }
+
+    // average uplift after each tree, [stage][row][arm]
+    pub fn predict_staged(& mut self, py: Python, headers: Vec<String>, arr: Vec<Vec<Option<Float>>>) -> PyResult<Vec<Vec<Vec<Float>>>> {
+        self.validate(&headers)?;
         let indices: Vec<Vec<String>> = Vec::new();
-        let loader = (*self.ptr).get_loader();
-        let m = loader.from_memory(&headers, &indices, &arr);
-        (*self.ptr).fit(m);
+        let ptr = &mut self.ptr;
+        Ok(py.allow_threads(|| {
+            let m = (*ptr).get_loader().from_memory(&headers, &indices, &arr);
+            (*ptr).predict_staged(m)
+        }))
+    }
+
+    // uplift of every tree, [tree][row][arm]
+    pub fn predict_per_tree(& mut self, py: Python, headers: Vec<String>, arr: Vec<Vec<Option<Float>>>) -> PyResult<Vec<Vec<Vec<Float>>>> {
+        self.validate(&headers)?;
+        let indices: Vec<Vec<String>> = Vec::new();
+        let ptr = &mut self.ptr;
+        Ok(py.allow_threads(|| {
+            let m = (*ptr).get_loader().from_memory(&headers, &indices, &arr);
+            (*ptr).predict_per_tree(m)
+        }))
+    }
+
+    // expected response under the control and every treatment, [row][arm]
+    pub fn predict_outcomes(& mut self, py: Python, headers: Vec<String>, arr: Vec<Vec<Option<Float>>>) -> PyResult<Vec<Vec<Float>>> {
+        self.validate(&headers)?;
+        let indices: Vec<Vec<String>> = Vec::new();
+        let ptr = &mut self.ptr;
+        Ok(py.allow_threads(|| {
+            let m = (*ptr).get_loader().from_memory(&headers, &indices, &arr);
+            (*ptr).predict_outcomes(m)
+        }))
+    }
+
+    /*
+    a dict per row with the uplift, the bias, the contributions as (feature, uplift) pairs and
+    the path in every tree, each step holding a condition in original feature units
+    */
+    pub fn explain(& mut self, py: Python, headers: Vec<String>, arr: Vec<Vec<Option<Float>>>) -> PyResult<PyObject> {
+        self.validate(&headers)?;
+        let indices: Vec<Vec<String>> = Vec::new();
+        let ptr = &mut self.ptr;
+        let explanations: Vec<Explanation> = py.allow_threads(|| {
+            let m = (*ptr).get_loader().from_memory(&headers, &indices, &arr);
+            (0..m.size()).map(|row| (*ptr).explain(&m, row)).collect()
+        });
+
+        to_py_json(py, &explanations)
+    }
+
+    /*
+    TreeSHAP attributions, [row][feature][uplift] with features in the order of the model, and
+    the expected value they are relative to
+    */
+    pub fn shap_values(& mut self, py: Python, headers: Vec<String>, arr: Vec<Vec<Option<Float>>>)
+        -> PyResult<Attributions> {
+
+        self.validate(&headers)?;
+        let indices: Vec<Vec<String>> = Vec::new();
+        let ptr = &mut self.ptr;
+        Ok(py.allow_threads(|| {
+            let m = (*ptr).get_loader().from_memory(&headers, &indices, &arr);
+            ((*ptr).shap_values(m), (*ptr).expected_value())
+        }))
+    }
+
+    /*
+    permutation importance on held out rows, which need the treatment and response columns.
+    metric is qini, auuc or transformed_outcome_mse, and a dict per feature gives the mean drop,
+    its standard error and the drop of every repeat
+    */
+    #[pyo3(signature = (headers, arr, metric="qini", n_repeat=5))]
+    pub fn permutation_importance(& mut self, py: Python, headers: Vec<String>, arr: Vec<Vec<Option<Float>>>,
+        metric: &str, n_repeat: usize) -> PyResult<PyObject> {
+
+        let metric: UpliftMetric = serde_json::from_value(serde_json::Value::String(metric.to_string()))
+            .map_err(|_| PyValueError::new_err(format!("unknown metric {}", metric)))?;
+        if n_repeat == 0 {
+            return Err(PyValueError::new_err("n_repeat must be positive"));
+        }
+
+        self.validate(&headers)?;
+        let indices: Vec<Vec<String>> = Vec::new();
+        let ptr = &mut self.ptr;
+        let importances: Option<Vec<Importance>> = py.allow_threads(|| {
+            let m = (*ptr).get_loader().from_memory(&headers, &indices, &arr);
+            match m.treatments.is_empty() || m.response.is_empty() {
+                true => None,
+                false => Some((*ptr).permutation_importance(&m, metric, n_repeat))
+            }
+        });
+
+        match importances {
+            Some(x) => to_py_json(py, &x),
+            None => Err(PyValueError::new_err("the treatment and response columns are needed"))
+        }
+    }
+
+    /*
+    partial dependence of the uplift on one or two features over their bins. the dict holds the
+    grid of every feature, each point with its bin and the original values in it, the average
+    uplift at every combination of points, [point][arm] with the last feature varying fastest,
+    and with ice the curves of the rows, [row][point][arm]
+    */
+    #[pyo3(signature = (headers, arr, features, ice=false))]
+    pub fn partial_dependence(& mut self, py: Python, headers: Vec<String>, arr: Vec<Vec<Option<Float>>>,
+        features: Vec<String>, ice: bool) -> PyResult<PyObject> {
+
+        if features.is_empty() || features.len() > 2 {
+            return Err(PyValueError::new_err("partial dependence takes one or two features"));
+        }
+
+        self.validate(&headers)?;
+        let indices: Vec<Vec<String>> = Vec::new();
+        let ptr = &mut self.ptr;
+        let pd: Result<PartialDependence, String> = py.allow_threads(|| {
+            let m = (*ptr).get_loader().from_memory(&headers, &indices, &arr);
+            let mut ids: Vec<usize> = Vec::new();
+            for feature in features.iter() {
+                match m.name.iter().position(|x| x == feature) {
+                    Some(k) => ids.push(k),
+                    None => return Err(format!("{} is not a feature of the model", feature))
+                }
+            }
+            Ok((*ptr).partial_dependence(&m, &ids, ice))
+        });
+
+        match pd {
+            Ok(x) => to_py_json(py, &x),
+            Err(e) => Err(PyValueError::new_err(e))
+        }
+    }
+
+    /*
+    the leaves of the forest as segments, a dict per rule with its text, clauses in original
+    feature units, uplift per treatment, support and the number of leaves merged into it, ranked
+    by the uplift of arm and then support. rules with less than min_support are dropped, and
+    neighbouring rules whose uplifts are within tolerance are merged. the options are keyword
+    arguments: min_support=0.0, tolerance=None, max_rules=None and arm=0
+    */
+    #[pyo3(signature = (**options))]
+    pub fn rules(& mut self, py: Python, options: Option<&Bound<'_, PyDict>>) -> PyResult<PyObject> {
+        let options = extract_rule_options(options, "rules")?;
+        let ptr = &mut self.ptr;
+        let rules = py.allow_threads(move || (*ptr).rules(&options));
+        to_py_json(py, &rules)
+    }
+
+    // writes the rules to path, as csv or json, with the options of rules
+    #[pyo3(signature = (path, format="csv", **options))]
+    pub fn export_rules(& mut self, py: Python, path: String, format: &str,
+        options: Option<&Bound<'_, PyDict>>) -> PyResult<()> {
+
+        let options = extract_rule_options(options, "export_rules")?;
+        let ptr = &mut self.ptr;
+        let rules = py.allow_threads(move || (*ptr).rules(&options));
+        let content = match format {
+            "csv" => to_csv(&rules),
+            "json" => to_json(&rules),
+            x => return Err(PyValueError::new_err(format!("unknown format {}", x)))
+        };
+
+        std::fs::write(&path, content).map_err(|e| PyIOError::new_err(e.to_string()))
+    }
+
+    /*
+    a single regression tree fitted to the uplift of the model on the rows of arr, returned as a
+    model of its own with its in-sample R² against this model for every treatment, measured on the
+    rows of arr it was fitted to. its outcomes are those of this model under control plus the uplift
+    */
+    #[pyo3(signature = (headers, arr, max_depth=3, min_samples_leaf=100))]
+    pub fn distill(& mut self, py: Python, headers: Vec<String>, arr: Vec<Vec<Option<Float>>>, max_depth: usize,
+        min_samples_leaf: usize) -> PyResult<(CausalModel, Vec<Float>)> {
+
+        self.validate(&headers)?;
+        let indices: Vec<Vec<String>> = Vec::new();
+        let ptr = &mut self.ptr;
+        let (ptr, in_sample) = py.allow_threads(|| {
+            let m = (*ptr).get_loader().from_memory(&headers, &indices, &arr);
+            (*ptr).distill(&m, max_depth, min_samples_leaf)
+        });
+
+        Ok((CausalModel { ptr }, in_sample))
+    }
+
+    // tree as indented text, every node with its rows, outcome and uplift per arm
+    #[pyo3(signature = (tree=0))]
+    pub fn tree_to_text(& self, tree: usize) -> PyResult<String> {
+        (*self.ptr).tree_to_text(tree).ok_or(PyValueError::new_err(format!("there is no tree {}", tree)))
+    }
+
+    // tree as graphviz dot, for graphviz.Source or the dot command
+    #[pyo3(signature = (tree=0))]
+    pub fn tree_to_dot(& self, tree: usize) -> PyResult<String> {
+        (*self.ptr).tree_to_dot(tree).ok_or(PyValueError::new_err(format!("there is no tree {}", tree)))
+    }
+
+    // a sql query scoring table, a table name or a parenthesised subquery, with the model
+    pub fn to_sql(& self, table: &str) -> String {
+        (*self.ptr).to_sql(table)
+    }
+
+    // id of the leaf node every row falls in, [row][tree]
+    pub fn apply(& mut self, py: Python, headers: Vec<String>, arr: Vec<Vec<Option<Float>>>) -> PyResult<Vec<Vec<usize>>> {
+        self.validate(&headers)?;
+        let indices: Vec<Vec<String>> = Vec::new();
+        let ptr = &mut self.ptr;
+        Ok(py.allow_threads(|| {
+            let m = (*ptr).get_loader().from_memory(&headers, &indices, &arr);
+            (*ptr).apply(m)
+        }))
+    }
+
+    // for every row of query, (row of train, weight) pairs adding up to 1. both share headers
+    pub fn forest_weights(& mut self, py: Python, headers: Vec<String>, train: Vec<Vec<Option<Float>>>,
+        query: Vec<Vec<Option<Float>>>) -> PyResult<Vec<Vec<(usize, Float)>>> {
+
+        self.validate(&headers)?;
+        let indices: Vec<Vec<String>> = Vec::new();
+        let ptr = &mut self.ptr;
+        Ok(py.allow_threads(|| {
+            let loader = (*ptr).get_loader();
+            let train = loader.from_memory(&headers, &indices, &train);
+            let query = loader.from_memory(&headers, &indices, &query);
+            (*ptr).forest_weights(&train, &query)
+        }))
+    }
+}
+
+// TreeSHAP attributions, [row][feature][uplift], and the expected value
+type Attributions = (Vec<Vec<Vec<Float>>>, Vec<Float>);
+
+struct FitOptions {
+    callback: Option<PyObject>,
+    oob: bool,
+    log_splits: bool,
+    valid: Option<Vec<Vec<Option<Float>>>>,
+    early_stopping: Option<EarlyStopping>,
+}
+
+impl FitOptions {
+
+    // from the keyword arguments of fit and fit_more
+    fn extract(kwargs: Option<&Bound<'_, PyDict>>, method: &str) -> PyResult<FitOptions> {
+        check_kwargs(kwargs, method, &["callback", "oob", "log_splits", "valid", "patience", "min_delta"])?;
+
+        let patience: Option<usize> = extract_kwarg(kwargs, "patience", None)?;
+        let min_delta: Float = extract_kwarg(kwargs, "min_delta", 0.0)?;
+        Ok(FitOptions {
+            callback: extract_kwarg(kwargs, "callback", None)?,
+            oob: extract_kwarg(kwargs, "oob", false)?,
+            log_splits: extract_kwarg(kwargs, "log_splits", false)?,
+            valid: extract_kwarg(kwargs, "valid", None)?,
+            early_stopping: patience.map(|x| EarlyStopping::new(x, min_delta)).transpose().map_err(PyValueError::new_err)?
+        })
+    }
+}
+
+impl CausalModel {
+
+    // raises the error the loader would panic with on these headers
+    fn validate(& mut self, headers: &[String]) -> PyResult<()> {
+        (*self.ptr).get_loader().validate(headers).map_err(PyValueError::new_err)
+    }
+
+    // fits, or adds n_more trees when it is set
+    fn run_fit(& mut self, py: Python, headers: Vec<String>, arr: Vec<Vec<Option<Float>>>,
+        n_more: Option<usize>, options: FitOptions) -> PyResult<()> {
+
+        self.validate(&headers)?;
+        let indices: Vec<Vec<String>> = Vec::new();
+        let control = Control::new();
+        let saved = (*self.ptr).get_loader().clone();
+        let raised: Mutex<Option<PyErr>> = Mutex::new(None);
+
+        let ptr = &mut self.ptr;
+        let result = run_interruptible(py, &control, || {
+            let loader = (*ptr).get_loader();
+            let m = loader.from_memory(&headers, &indices, &arr);
+            let valid = options.valid.as_ref().map(|x| loader.from_memory(&headers, &indices, x));
+
+            let mut monitor = match options.callback.as_ref() {
+                Some(callback) => FitMonitor::new(|info| Python::with_gil(|py| {
+                    let result = tree_info_to_dict(py, info).and_then(|x| callback.call1(py, (x,)));
+                    match result {
+                        Ok(x) if x.bind(py).eq(false).unwrap_or(false) => Action::Stop,
+                        Ok(_) => Action::Continue,
+                        Err(e) => {
+                            *raised.lock().unwrap() = Some(e);
+                            control.cancel();
+                            Action::Stop
+                        }
+                    }
+                })),
+                None => FitMonitor::default()
+            };
+            monitor.oob = options.oob;
+            monitor.log_splits = options.log_splits;
+            monitor.validation = valid.as_ref();
+            monitor.early_stopping = options.early_stopping;
+
+            match n_more {
+                Some(n_tree) => (*ptr).fit_more(m, n_tree, &control, &mut monitor),
+                None => (*ptr).fit(m, &control, &mut monitor)
+            }
+        });
+
+        // a cancelled fit leaves the learners as they were, and the bins fitted on m are undone
+        if result.is_err() {
+            *(*self.ptr).get_loader() = saved;
+        }
+
+        match raised.into_inner().unwrap() {
+            Some(e) => Err(e),
+            None => result
+        }
+    }
+}
+
+#[pyclass]
+pub struct MappedModel {
+    forest: MappedForest,
+}
+
+#[pymethods]
+impl MappedModel {
+
+    #[new]
+    #[pyo3(signature = (path, verify=true))]
+    pub fn new(path: String, verify: bool) -> PyResult<MappedModel> {
+        let forest = MappedForest::open(&path, verify).map_err(to_py_err)?;
+        Ok(MappedModel { forest })
     }
 
-    pub fn predict(& mut self, headers: Vec<String>, arr: Vec<Vec<Option<Float>>>) -> Vec<Vec<Float>> {
+    pub fn predict(& mut self, py: Python, headers: Vec<String>, arr: Vec<Vec<Option<Float>>>) -> PyResult<Vec<Vec<Float>>> {
+        self.forest.loader.validate(&headers).map_err(PyValueError::new_err)?;
         let indices: Vec<Vec<String>> = Vec::new();
-        let loader = (*self.ptr).get_loader();
-        let m = loader.from_memory(&headers, &indices, &arr);
-        let score = (*self.ptr).predict(m);
-        score
+        let forest = &mut self.forest;
+        Ok(py.allow_threads(|| {
+            let m = forest.loader.from_memory(&headers, &indices, &arr);
+            forest.predict(&m)
+        }))
     }
 }
 
 #[pymodule]
 fn causalkit(_py: Python, m: &PyModule) -> PyResult<()> {
     m.add_class::<CausalModel>()?;
+    m.add_class::<MappedModel>()?;
     Ok(())
 }
@@ -1,14 +1,21 @@
+use std::cmp;
+
 use crate::split_info::SplitInfo;
 use crate::config::{Int, Float, TreeConfig};
 use crate::strategy::Strategy;
 use crate::dmatrix::DMatrix;
 use crate::statistic::{Sum, SecondOrderSum, CountNoY};
+use crate::histogram::Histogram;
+
+// a candidate left child: (split value, sum, second order sum, count)
+type Candidate = (Vec<Int>, Vec<Float>, Vec<Float>, Vec<Float>);
 
 #[derive(Clone)]
 pub struct RegressionStrategy {
     pub min_samples_leaf: usize,
     pub min_samples_treatment: usize,
-    pub alpha: Float
+    pub alpha: Float,
+    pub cat_partition: bool
 }
 
 impl Strategy for RegressionStrategy {
@@ -18,111 +25,196 @@ impl Strategy for RegressionStrategy {
         let min_samples_leaf = conf.min_samples_leaf;
         let min_samples_treatment = conf.min_samples_treatment;
         let alpha = conf.alpha;
-        RegressionStrategy { min_samples_leaf, min_samples_treatment, alpha }
+        let cat_partition = conf.cat_partition;
+        RegressionStrategy { min_samples_leaf, min_samples_treatment, alpha, cat_partition }
+    }
+
+    fn n_channel(& self) -> usize {
+        3
+    }
+
+    // channels of Sum, SecondOrderSum and CountNoY
+    fn row_stat(& self, y: Float, weight: Float, value: &mut [Float]) {
+        value[0] = y * weight;
+        value[1] = y.powi(2) * weight;
+        value[2] = weight;
     }
 
-    fn find_best_split(& self, m: &DMatrix, node_id: usize, feature_id: usize, 
-        indices: &mut [usize]) -> Self::T {
+    fn find_best_split_hist(& self, m: &DMatrix, node_id: usize, feature_id: usize, 
+        hist: &Histogram) -> Self::T {
 
-        let iscat = m.is_bool.get(feature_id).unwrap().clone();
-        let name = m.name.get(feature_id).unwrap();
-        let feature_size = m.bin_size.get(name).unwrap().clone();
+        let iscat = m.is_bool[feature_id];
         let treatment_id = 0;
-        let treatment_size = m.treatment_size.get(treatment_id).unwrap().clone();
 
-        let sum = Sum::calculate(m, feature_id, iscat, treatment_id, indices,
-            feature_size, treatment_size);
+        let sum = Sum::from_histogram(hist, 0, feature_id, iscat, treatment_id);
+        let moment = SecondOrderSum::from_histogram(hist, 1, feature_id, iscat, treatment_id);
+        let count = CountNoY::from_histogram(hist, 2, feature_id, iscat, treatment_id);
 
-        let moment = SecondOrderSum::calculate(m, feature_id, iscat, treatment_id, indices,
-            feature_size, treatment_size);
+        self.split_info(node_id, feature_id, treatment_id, &sum, &moment, &count)
+    }
+}
 
-        let count = CountNoY::calculate(m, feature_id, iscat, treatment_id, indices,
-            feature_size, treatment_size);
+impl RegressionStrategy {
 
-        let (value, gain, gain_importance) = self.find_best_split_plain(&sum, &moment, &count);
+    fn split_info(& self, node_id: usize, feature_id: usize, treatment_id: usize, sum: &Sum, 
+        moment: &SecondOrderSum, count: &CountNoY) -> SplitInfo {
+
+        let iscat = sum.iscat;
+        let (value, missing_left, gain, gain_importance) = self.find_best_split_plain(sum, moment, count);
 
-        let parent_sum = self.get_parent_stat(iscat, & sum.stat);
-        let parent_count = self.get_parent_stat(iscat, & count.stat);
+        let parent_sum = self.get_parent_stat(& sum.stat);
+        let parent_count = self.get_parent_stat(& count.stat);
 
         let mut summary: Vec<Vec<Float>> = Vec::new();
         let n_treatment = parent_sum.len();
         for i in 0..n_treatment {
             let mut mean = Vec::new();
             mean.push(parent_sum[i] / parent_count[i]);
+            // weighted count of the arm, the cover of the node for TreeSHAP
+            mean.push(parent_count[i]);
             summary.push(mean);
         }
 
-        SplitInfo { node_id, feature_id, treatment_id, iscat, value, gain, gain_importance, summary }
+        SplitInfo { node_id, feature_id, treatment_id, iscat, value, missing_left, gain, gain_importance, summary }
     }
-}
 
-impl RegressionStrategy {
     fn find_best_split_plain(& self, sum: &Sum, moment: &SecondOrderSum, count: &CountNoY) -> 
-        (Option<Vec<Int>>, Float, Float) {
+        (Option<Vec<Int>>, bool, Float, Float) {
 
         let mut best_gain = 0.0;
         let mut best_gain_importance = 0.0;
         let mut best_split_value = None;
+        let mut best_missing_left = false;
 
         let iscat = sum.iscat;
 
-        let parent_sum = self.get_parent_stat(iscat, & sum.stat);
-        let parent_moment = self.get_parent_stat(iscat, & moment.stat);
-        let parent_count = self.get_parent_stat(iscat, & count.stat);
+        let parent_sum = self.get_parent_stat(& sum.stat);
+        let parent_moment = self.get_parent_stat(& moment.stat);
+        let parent_count = self.get_parent_stat(& count.stat);
         let parent_score = self.get_impurity(& parent_sum, & parent_moment, & parent_count);
         
         let n_splits = sum.stat[0].len() - 1;
-        for pos in 0..n_splits {
-            let left_sum = self.get_stat(& sum.stat, pos);
-            let left_moment = self.get_stat(& moment.stat, pos);
-            let left_count = self.get_stat(& count.stat, pos);
-            let left_score = self.get_impurity(& left_sum, & left_moment, & left_count);
-
-            let right_sum = self.get_difference(& parent_sum, & left_sum);
-            let right_moment = self.get_difference(& parent_moment, & left_moment);
-            let right_count = self.get_difference(& parent_count, & left_count);
-            let right_score = self.get_impurity(& right_sum, & right_moment, & right_count);
-
-            // https://scikit-learn.org/stable/modules/generated/sklearn.tree.DecisionTreeClassifier.html
-            // N_t / N is ignored since there is no pruning, comparison across nodes is not necessary
-            // only support control and one treatment
-            let n_t_l = left_count[0] + left_count[1];
-            let n_t_r = right_count[0] + right_count[1];
-            let n_t = parent_count[0] + parent_count[1];
-
-            let gain = parent_score - left_score * n_t_l / n_t - right_score * n_t_r / n_t;
-
-            if gain > best_gain {
-                best_gain = gain;
-                best_gain_importance = gain;
-
-                let mut value = Vec::new();
-                value.push(pos as Int);                    
-                best_split_value = Some(value);
+        let missing_sum = self.get_missing_stat(& sum.stat);
+        let missing_moment = self.get_missing_stat(& moment.stat);
+        let missing_count = self.get_missing_stat(& count.stat);
+        let has_missing = missing_count.iter().any(|&x| x > 0.0);
+
+        let candidates: Vec<Candidate> = match iscat {
+            x if x && self.cat_partition => self.get_partition_candidates(sum, moment, count, & parent_sum, & parent_count),
+            x if x => (0..n_splits).map(|pos| (vec![pos as Int], self.get_stat(& sum.stat, pos),
+                self.get_stat(& moment.stat, pos), self.get_stat(& count.stat, pos))).collect(),
+            _ => self.get_cumulative_candidates(sum, moment, count)
+        };
+
+        for (value, left_sum, left_moment, left_count) in candidates.iter() {
+            // evaluate missing values going right first, so ties keep the default direction
+            let mut directions = vec![(false, left_sum.clone(), left_moment.clone(), left_count.clone())];
+            if has_missing {
+                directions.push((true, self.get_addition(left_sum, & missing_sum),
+                    self.get_addition(left_moment, & missing_moment),
+                    self.get_addition(left_count, & missing_count)));
+            }
+
+            for (missing_left, left_sum, left_moment, left_count) in directions.iter() {
+                let left_score = self.get_impurity(left_sum, left_moment, left_count);
+
+                let right_sum = self.get_difference(& parent_sum, left_sum);
+                let right_moment = self.get_difference(& parent_moment, left_moment);
+                let right_count = self.get_difference(& parent_count, left_count);
+                let right_score = self.get_impurity(& right_sum, & right_moment, & right_count);
+
+                // https://scikit-learn.org/stable/modules/generated/sklearn.tree.DecisionTreeClassifier.html
+                // N_t / N is ignored since there is no pruning, comparison across nodes is not necessary
+                // only support control and one treatment
+                let n_t_l = left_count[0] + left_count[1];
+                let n_t_r = right_count[0] + right_count[1];
+                let n_t = parent_count[0] + parent_count[1];
+
+                let gain = parent_score - left_score * n_t_l / n_t - right_score * n_t_r / n_t;
+
+                if gain > best_gain {
+                    best_gain = gain;
+                    best_gain_importance = gain;
+                    best_missing_left = *missing_left;
+                    best_split_value = Some(value.clone());
+                }
             }
         }
     
-        (best_split_value, best_gain, best_gain_importance)
+        (best_split_value, best_missing_left, best_gain, best_gain_importance)
+    }
+
+    // the bins up to every position as the left child, for continuous features
+    fn get_cumulative_candidates(& self, sum: &Sum, moment: &SecondOrderSum, count: &CountNoY) -> Vec<Candidate> {
+        let n_splits = sum.stat[0].len() - 1;
+        let n_treatment = sum.stat.len();
+
+        let mut left_sum = vec![0.0; n_treatment];
+        let mut left_moment = vec![0.0; n_treatment];
+        let mut left_count = vec![0.0; n_treatment];
+        (0..n_splits).map(|pos| {
+            for t in 0..n_treatment {
+                left_sum[t] += sum.stat[t][pos];
+                left_moment[t] += moment.stat[t][pos];
+                left_count[t] += count.stat[t][pos];
+            }
+            (vec![pos as Int], left_sum.clone(), left_moment.clone(), left_count.clone())
+        }).collect()
     }
 
-    fn get_parent_stat(& self, iscat: bool, stat: &Vec<Vec<Float>>) -> Vec<Float> {
-        let mut parent_stat = Vec::new();
-        for (_idx_t, arr1) in stat.iter().enumerate() {
-            let c = match iscat {
-                x if x => {
-                    let v: Vec<f32> = arr1.iter().map(|&x| x as f32).collect();
-                    v.iter().sum::<f32>() as Float
-                },
-                _ => *arr1.last().unwrap()
-            };
+    // order the categories by their uplift against control, and return every prefix of that order
+    // as a candidate left child
+    fn get_partition_candidates(& self, sum: &Sum, moment: &SecondOrderSum, count: &CountNoY, 
+        parent_sum: &[Float], parent_count: &[Float]) -> Vec<Candidate> {
+
+        let n_bin = sum.stat[0].len() - 1;
+        let n_treatment = parent_sum.len();
+
+        let mut uplift: Vec<(usize, Float)> = Vec::new();
+        for pos in 0..n_bin {
+            let bin_sum = self.get_stat(& sum.stat, pos);
+            let bin_count = self.get_stat(& count.stat, pos);
+            if bin_count.iter().sum::<Float>() <= 0.0 {
+                continue;
+            }
+
+            // arms without samples in this category fall back to the node mean
+            let mean: Vec<Float> = (0..n_treatment).map(|t| match bin_count[t] {
+                c if c > 0.0 => bin_sum[t] / c,
+                _ => parent_sum[t] / parent_count[t]
+            }).collect();
 
-            parent_stat.push(c);
+            let n_arm = cmp::max(n_treatment - 1, 1) as Float;
+            let u: Float = mean.iter().skip(1).map(|x| x - mean[0]).sum::<Float>() / n_arm;
+            uplift.push((pos, u));
         }
 
-        parent_stat
+        uplift.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(cmp::Ordering::Equal));
+
+        let mut candidates = Vec::new();
+        let mut value: Vec<Int> = Vec::new();
+        let mut left_sum = vec![0.0; n_treatment];
+        let mut left_moment = vec![0.0; n_treatment];
+        let mut left_count = vec![0.0; n_treatment];
+        for (pos, _) in uplift.iter() {
+            value.push(*pos as Int);
+            left_sum = self.get_addition(& left_sum, & self.get_stat(& sum.stat, *pos));
+            left_moment = self.get_addition(& left_moment, & self.get_stat(& moment.stat, *pos));
+            left_count = self.get_addition(& left_count, & self.get_stat(& count.stat, *pos));
+
+            let mut categories = value.clone();
+            categories.sort();
+            candidates.push((categories, left_sum.clone(), left_moment.clone(), left_count.clone()));
+        }
+
+        candidates
     }
 
-    fn get_stat(& self, stat: &Vec<Vec<Float>>, pos: usize) -> Vec<Float> {
+    fn get_parent_stat(& self, stat: &[&[Float]]) -> Vec<Float> {
+        stat.iter().map(|arr1| arr1.iter().sum()).collect()
+    }
+
+    fn get_stat(& self, stat: &[&[Float]], pos: usize) -> Vec<Float> {
         let mut count = Vec::new();
         for (_idx_t, arr) in stat.iter().enumerate() {
             count.push(arr[pos]);
@@ -131,6 +223,15 @@ impl RegressionStrategy {
         count
     }
 
+    // statistics of the missing bin, which is the last slot of the histogram
+    fn get_missing_stat(& self, stat: &[&[Float]]) -> Vec<Float> {
+        stat.iter().map(|arr1| arr1[arr1.len() - 1]).collect()
+    }
+
+    fn get_addition(& self, stat: &[Float], other: &[Float]) -> Vec<Float> {
+        stat.iter().zip(other.iter()).map(|(&x, &y)| x + y).collect()
+    }
+
     fn get_difference(& self, parent_stat: &Vec<Float>, child_stat: &Vec<Float>) -> Vec<Float> {
         let mut diff: Vec<Float> = Vec::new();
 
@@ -159,3 +260,43 @@ impl RegressionStrategy {
         impurity
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture;
+
+    fn split(cat_partition: bool, iscat: bool, hist: &Histogram) -> (Option<Vec<Int>>, bool) {
+        let mut conf = fixture::conf("yr", 1);
+        conf.cat_partition = cat_partition;
+        let strategy = RegressionStrategy::new(&conf);
+
+        let sum = Sum::from_histogram(hist, 0, 0, iscat, 0);
+        let moment = SecondOrderSum::from_histogram(hist, 1, 0, iscat, 0);
+        let count = CountNoY::from_histogram(hist, 2, 0, iscat, 0);
+        let (value, missing_left, _, _) = strategy.find_best_split_plain(&sum, &moment, &count);
+        (value, missing_left)
+    }
+
+    #[test]
+    fn test_missing_left() {
+        let strategy = RegressionStrategy::new(&fixture::conf("yr", 1));
+
+        // the missing values are uplifted like the first bin, and join it on the left
+        let hist = fixture::histogram(&strategy, 4, 4, &[0, 3]);
+        assert_eq!(split(false, false, &hist), (Some(vec![0]), true));
+
+        let hist = fixture::histogram(&strategy, 4, 4, &[0]);
+        assert_eq!(split(false, false, &hist), (Some(vec![0]), false));
+    }
+
+    #[test]
+    fn test_partition() {
+        let strategy = RegressionStrategy::new(&fixture::conf("yr", 1));
+        let hist = fixture::histogram(&strategy, 6, 5, &[1, 3]);
+
+        assert_eq!(split(true, true, &hist), (Some(vec![1, 3]), false));
+        // one category against the rest without the partition
+        assert_eq!(split(false, true, &hist).0.unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,108 @@
+use crate::config::Float;
+use crate::data_loader::DataLoader;
+use crate::split_info::SplitInfoTrait;
+use crate::tree::Tree;
+use crate::explain::{Condition, decode, node_uplift};
+
+const INDENT: &str = "    ";
+
+// the statistics of a node: rows and outcome of every arm, then uplift against the control
+fn describe_node<S: SplitInfoTrait>(n: usize, split_info: &S) -> Vec<String> {
+    let summary = split_info.get_summary();
+    let list = |values: Vec<Float>, digits: usize| {
+        let items: Vec<String> = values.iter().map(|x| format!("{:.*}", digits, x)).collect();
+        format!("[{}]", items.join(", "))
+    };
+
+    let mut lines = vec![format!("node {}", n)];
+    // regression models saved before the counts were kept have the outcome only
+    if summary.iter().all(|x| x.len() > 1) {
+        lines.push(format!("n = {}", list(summary.iter().map(|x| x[1]).collect(), 0)));
+    }
+    lines.push(format!("outcome = {}", list(summary.iter().map(|x| x[0]).collect(), 4)));
+    lines.push(format!("uplift = {}", list(node_uplift(split_info), 4)));
+    lines
+}
+
+// the condition of the left or right branch of a split, missing values included where they go
+fn branch<S: SplitInfoTrait>(split_info: &S, loader: &DataLoader, left: bool) -> String {
+    let feature = &loader.features[split_info.get_feature_id()];
+    let value = split_info.get_value().unwrap();
+    let condition = decode(loader, feature, split_info.get_iscat(), &value, left);
+    let missing = split_info.get_missing_left() == left;
+
+    // a continuous split past the last threshold only separates the missing values
+    match condition {
+        Condition::Less(x) if x == Float::MAX && !missing => format!("{} is not missing", feature),
+        Condition::GreaterEqual(x) if x == Float::MAX && missing => Condition::Missing.describe(feature),
+        _ if missing => format!("{} or {}", condition.describe(feature), Condition::Missing.describe(feature)),
+        _ => condition.describe(feature)
+    }
+}
+
+fn write_text<T: Tree>(tree: &T, loader: &DataLoader, n: usize, depth: usize, out: &mut String) {
+    let split_info = tree.splits()[n].as_ref().unwrap();
+    let indent = INDENT.repeat(2 * depth);
+    out.push_str(&format!("{}{}\n", indent, describe_node(n, split_info).join(", ")));
+
+    if let (Some(l), Some(r)) = (tree.left_children()[n], tree.right_children()[n]) {
+        for (child, left) in [(l, true), (r, false)] {
+            out.push_str(&format!("{}{}{}:\n", indent, INDENT, branch(split_info, loader, left)));
+            write_text(tree, loader, child, depth + 1, out);
+        }
+    }
+}
+
+/*
+the tree as indented text: every node with its statistics, and under a split the condition of
+each branch followed by the subtree it leads to, left first
+*/
+pub fn to_text<T: Tree>(tree: &T, loader: &DataLoader) -> String {
+    let mut out = String::new();
+    write_text(tree, loader, 0, 0, &mut out);
+    out
+}
+
+// labels are quoted in dot, so quotes and backslashes are escaped
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/*
+the tree as a graphviz digraph, a box per node with its statistics, leaves rounded, and the
+condition of each branch on its edge
+*/
+pub fn to_dot<T: Tree>(tree: &T, loader: &DataLoader) -> String {
+    let left_children = tree.left_children();
+    let right_children = tree.right_children();
+    let splits = tree.splits();
+
+    let mut lines: Vec<String> = vec![
+        "digraph tree {".to_string(),
+        format!("{}node [shape=box, fontname=\"helvetica\"];", INDENT),
+        format!("{}edge [fontname=\"helvetica\"];", INDENT)
+    ];
+
+    for n in 0..splits.len() {
+        let split_info = match splits[n].as_ref() {
+            Some(x) => x,
+            None => continue
+        };
+        let label: Vec<String> = describe_node(n, split_info).iter().map(|x| escape(x)).collect();
+        let style = match left_children[n] {
+            Some(_) => "",
+            None => ", style=rounded"
+        };
+        lines.push(format!("{}{} [label=\"{}\"{}];", INDENT, n, label.join("\\n"), style));
+
+        if let (Some(l), Some(r)) = (left_children[n], right_children[n]) {
+            for (child, left) in [(l, true), (r, false)] {
+                let condition = escape(&branch(split_info, loader, left));
+                lines.push(format!("{}{} -> {} [label=\"{}\"];", INDENT, n, child, condition));
+            }
+        }
+    }
+
+    lines.push("}".to_string());
+    lines.join("\n") + "\n"
+}
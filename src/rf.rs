@@ -1,111 +1,209 @@
 use std::fs::File;
-use rand::{rngs::StdRng, SeedableRng};
+use std::cmp;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
 use std::sync::{Arc, Mutex};
 use std::vec;
-use std::thread;
 use std::io::prelude::*;
 use std::collections::HashMap;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Instant;
+use std::ops::Range;
 
 use crate::data_loader::DataLoader;
-use crate::bin::{DiscreteBin, ContinuousBin};
-use crate::tree::Tree;
+use crate::bin::{DiscreteBin, ContinuousBin, CategoryEncoder};
+use crate::tree::{Tree, RegressionTree};
+use crate::regression::RegressionStrategy;
 use crate::strategy::Strategy;
-use crate::config::{Float, TreeConfig};
+use crate::config::{Int, Float, TreeConfig};
 use crate::learn::TreeLearn;
 use crate::dmatrix::DMatrix;
 use crate::random::Random;
-use crate::linalg::Matrix;
+use crate::format::{ModelError, ModelHeader, MAGIC};
+use crate::flat::{FlatTree, FlatSource, write_binary};
+use crate::control::{Control, Cancelled, FitMonitor, TreeInfo, Action};
+use crate::metric::{OutOfBag, UpliftMetric, r_squared};
+use crate::explain::{Explanation, explain};
+use crate::shap::{ShapTree, shap_values};
+use crate::importance::{Importance, permutation_importance};
+use crate::pdp::{PartialDependence, partial_dependence};
+use crate::rules::{Rule, RuleOptions, extract_rules};
+use crate::distill::fit_surrogate;
+use crate::render::{to_text, to_dot};
+use crate::sql::forest_to_sql;
+
+pub const MODEL_TYPE: &str = "RandomForest";
+
+const PREDICT_CHUNK_SIZE: usize = 1024;
 
 
 pub struct RandomForest<T: Tree + Clone + std::marker::Send, S: Strategy + Clone + std::marker::Send> {
     pub conf: TreeConfig,
     pub loader: DataLoader, 
-    pub learners: Vec<TreeLearn<T, S>>
+    pub learners: Vec<TreeLearn<T, S>>,
+    // the pool of the last n_thread it was built for, shared by the calls of the model
+    pool: Mutex<Option<(usize, Arc<ThreadPool>)>>
 }
 
 impl<T: Tree + Clone + std::marker::Send + 'static, S: Strategy + Clone + std::marker::Send + 'static> RandomForest<T, S> {
 
     pub fn new(conf: TreeConfig) -> RandomForest<T, S> {
+        let mut conf = conf;
+        conf.int_bits = Int::BITS;
+
         let bins_cont: HashMap<String, ContinuousBin> = HashMap::new();
         let bins_disc: HashMap<String, DiscreteBin> = HashMap::new();
 
-        let loader = DataLoader::new(conf.feature_cols.clone(), conf.y_col.clone(), conf.treatment_cols.clone(),
-            conf.weight_col.clone(), conf.cat_cols.clone(), conf.n_bin, bins_cont, bins_disc);
+        let encoders: HashMap<String, CategoryEncoder> = HashMap::new();
+
+        let loader = DataLoader::from_config(&conf, bins_cont, bins_disc, encoders);
 
         let learners: Vec<TreeLearn<T, S>> = Vec::new();
         let model: RandomForest<T, S> = RandomForest {
             conf,
             loader,
-            learners
+            learners,
+            pool: Mutex::new(None)
         };
 
         model
     }
 
-    pub fn from_string(ss: &String) -> RandomForest<T, S> {
+    /*
+    reads both the versioned format and the headerless format written by 0.1.x
+    */
+    pub fn from_string(ss: &String) -> Result<RandomForest<T, S>, ModelError> {
+        let rest = match ss.strip_prefix(MAGIC) {
+            Some(rest) => rest,
+            None => return RandomForest::from_body(ss)
+        };
+
+        let (line, body) = rest.trim_start_matches('\n').split_once('\n')
+            .ok_or(ModelError::Parse("header is missing".to_string()))?;
+        let header: ModelHeader = serde_json::from_str(line)?;
+        header.validate(MODEL_TYPE, T::name(), body)?;
+
+        let model = RandomForest::from_body(body)?;
+        header.validate_model(model.learners.len(), &model.loader)?;
+
+        Ok(model)
+    }
+
+    fn from_body(ss: &str) -> Result<RandomForest<T, S>, ModelError> {
         let lines: Vec<&str> = ss.split("\n\n").collect();
 
-        let line = lines.get(0).unwrap();
-        let conf: TreeConfig = serde_json::from_str(& line.to_string()).unwrap();
+        let line = lines.first().ok_or(ModelError::Parse("config is missing".to_string()))?;
+        let conf: TreeConfig = serde_json::from_str(line)?;
+        if conf.int_bits != Int::BITS {
+            return Err(ModelError::Mismatch(format!("model was trained with {}-bit bins but this build \
+                uses {}-bit bins, toggle the `wide-bin` feature to load it", conf.int_bits, Int::BITS)));
+        }
 
         let mut bins_cont: HashMap<String, ContinuousBin> = HashMap::new();
         let mut bins_disc: HashMap<String, DiscreteBin> = HashMap::new();
 
-        let n_feature = conf.feature_cols.len();
-        for n in 0..n_feature {
-            let line = lines.get(n + 1).unwrap();
+        let mut encoders: HashMap<String, CategoryEncoder> = HashMap::new();
+
+        // bin and encoder blocks come before the learners, whose first line is a json object
+        let mut n_block = 0;
+        while let Some(line) = lines.get(n_block + 1) {
             let fields: Vec<&str> = line.split('\n').collect();
-            let bin_type = fields.get(0).unwrap();
-            let name = fields.get(1).unwrap();
-            let content = fields.get(2).unwrap();
-
-            if bin_type.to_string() == String::from("Continuous") {
-                let bin: ContinuousBin = serde_json::from_str(& content.to_string()).unwrap();
-                bins_cont.insert(name.to_string(), bin);
-            } else if bin_type.to_string() == String::from("Discrete") {
-                let bin: DiscreteBin = serde_json::from_str(& content.to_string()).unwrap();
-                bins_disc.insert(name.to_string(), bin);
-            } else {
-                panic! ("bin_type {} unknown", bin_type);
+            let bin_type = fields[0];
+            if bin_type.starts_with('{') {
+                break;
             }
+
+            if fields.len() != 3 {
+                return Err(ModelError::Parse(format!("bin block {} is incomplete", n_block)));
+            }
+
+            let name = fields[1];
+            let content = fields[2];
+
+            match bin_type {
+                "Continuous" => {
+                    let bin: ContinuousBin = serde_json::from_str(content)?;
+                    bins_cont.insert(name.to_string(), bin);
+                },
+                "Discrete" => {
+                    let bin: DiscreteBin = serde_json::from_str(content)?;
+                    bins_disc.insert(name.to_string(), bin);
+                },
+                "Encoder" => {
+                    let encoder: CategoryEncoder = serde_json::from_str(content)?;
+                    encoders.insert(name.to_string(), encoder);
+                },
+                _ => return Err(ModelError::Parse(format!("bin_type {} unknown", bin_type)))
+            }
+
+            n_block += 1;
         }
 
-        let loader = DataLoader::new(conf.feature_cols.clone(), conf.y_col.clone(), conf.treatment_cols.clone(),
-            conf.weight_col.clone(), conf.cat_cols.clone(), conf.n_bin, bins_cont, bins_disc);
+        for name in conf.feature_cols.iter() {
+            if !bins_cont.contains_key(name) && !bins_disc.contains_key(name) {
+                return Err(ModelError::Parse(format!("bins of feature {} are missing", name)));
+            }
+        }
+
+        let loader = DataLoader::from_config(&conf, bins_cont, bins_disc, encoders);
 
         let mut learners: Vec<TreeLearn<T, S>> = Vec::new();
         let n_tree = conf.n_tree;
         for n in 0..n_tree {
-            let line = lines.get(n + 1 + n_feature).unwrap();
-            let learner: TreeLearn<T, S> = TreeLearn::from_string(& line.to_string());
+            let line = lines.get(n + 1 + n_block)
+                .ok_or(ModelError::Parse(format!("{} trees expected, {} found", n_tree, n)))?;
+            let learner: TreeLearn<T, S> = TreeLearn::from_string(line)?;
             learners.push(learner);
         }
 
         let model: RandomForest<T, S> = RandomForest {
             conf,
             loader,
-            learners
+            learners,
+            pool: Mutex::new(None)
         };
 
-        model
+        Ok(model)
     }
 
     pub fn to_string(& self) -> String {
+        let body = self.to_body();
+        let header = ModelHeader::new(MODEL_TYPE, T::name(), self.learners.len(), &self.loader, &body);
+        let line = serde_json::to_string(& header).unwrap();
+
+        format!("{}\n{}\n{}", MAGIC, line, body)
+    }
+
+    // bins and encoders are written in the order of the features, so the output is deterministic
+    fn to_body(& self) -> String {
         let mut ss = String::new();
 
         let line = serde_json::to_string(& self.conf).unwrap();
         let line = format!("{}\n\n", line);
         ss = ss + &line;
 
-        for (name, bin) in self.loader.bins_cont.iter() {
-            let line = serde_json::to_string(& bin).unwrap();
-            let line = format!("Continuous\n{}\n{}\n\n", name, line);
-            ss = ss + &line;
+        for name in self.loader.features.iter() {
+            if let Some(bin) = self.loader.bins_cont.get(name) {
+                let line = serde_json::to_string(& bin).unwrap();
+                let line = format!("Continuous\n{}\n{}\n\n", name, line);
+                ss = ss + &line;
+            }
+
+            if let Some(bin) = self.loader.bins_disc.get(name) {
+                let line = serde_json::to_string(& bin).unwrap();
+                let line = format!("Discrete\n{}\n{}\n\n", name, line);
+                ss = ss + &line;
+            }
         }
-        for (name, bin) in self.loader.bins_disc.iter() {
-            let line = serde_json::to_string(& bin).unwrap();
-            let line = format!("Discrete\n{}\n{}\n\n", name, line);
-            ss = ss + &line;
+
+        for name in self.loader.features.iter() {
+            if let Some(encoder) = self.loader.encoders.get(name) {
+                let line = serde_json::to_string(& encoder).unwrap();
+                let line = format!("Encoder\n{}\n{}\n\n", name, line);
+                ss = ss + &line;
+            }
         }
 
         for learner in self.learners.iter() {
@@ -117,12 +215,12 @@ impl<T: Tree + Clone + std::marker::Send + 'static, S: Strategy + Clone + std::m
         ss
     }
 
-    pub fn load(fname: &String) -> std::io::Result<RandomForest<T, S>> {
+    pub fn load(fname: &String) -> Result<RandomForest<T, S>, ModelError> {
         let mut file = File::open(fname.as_str())?;
         let mut ss = String::new();
         file.read_to_string(&mut ss)?;
 
-        let m: RandomForest<T, S> = RandomForest::from_string(&ss);
+        let m: RandomForest<T, S> = RandomForest::from_string(&ss)?;
 
         Ok(m)
     }
@@ -135,225 +233,586 @@ impl<T: Tree + Clone + std::marker::Send + 'static, S: Strategy + Clone + std::m
         Ok(())
     }
 
+    pub fn to_flat(& self) -> Vec<FlatTree> {
+        self.learners.iter().map(|x| FlatTree::compile(&x.tree)).collect()
+    }
+
+    /*
+    compact binary file that MappedForest scores without building the trees
+    */
+    pub fn save_binary(& self, fname: &String) -> std::io::Result<()> {
+        write_binary(fname, T::name(), &self.conf, &self.loader, &self.to_flat())
+    }
+
     pub fn fit(&mut self, m: DMatrix) {
-        let learners = match self.conf.n_thread {
-            n if n == 1 => self.fit_seq(m),
-            _ => self.fit_par(m)
-        };
+        self.fit_with(m, &Control::new()).unwrap()
+    }
 
-        self.learners.extend(learners);
+    /*
+    the control is checked before each tree, once it is cancelled no tree is started
+    and the learners are left as they were
+    */
+    pub fn fit_with(&mut self, m: DMatrix, control: &Control) -> Result<(), Cancelled> {
+        self.fit_monitored(m, control, &mut FitMonitor::default())
     }
 
-    pub fn predict(&mut self, m: DMatrix) -> Vec<Vec<Float>> {
-        match self.conf.n_thread {
-            n if n == 1 => self.predict_seq(m),
-            _ => self.predict_par(m)
-        }
+    /*
+    replaces the trees of the forest with conf.n_tree_configured new ones, or fewer when the monitor
+    stops the fit early. conf.n_tree is then the number of trees, and the configured number, taken
+    from n_tree at the first fit, is what the next fit grows
+    */
+    pub fn fit_monitored(&mut self, m: DMatrix, control: &Control, monitor: &mut FitMonitor) -> Result<(), Cancelled> {
+        let n_tree = self.conf.n_tree_configured.unwrap_or(self.conf.n_tree);
+        let learners = self.grow(&m, &[], n_tree, control, monitor)?;
+        self.learners = learners;
+        self.conf.n_tree_configured = Some(n_tree);
+        self.conf.n_tree = self.learners.len();
+        Ok(())
     }
 
-    fn fit_seq(&mut self, m: DMatrix) -> Vec<TreeLearn<T, S>> {
+    /*
+    warm start: adds up to n_tree trees grown on m, which is binned with the bins of the loader.
+    the new trees continue the seeds of the first fit, so with a seed, n trees and then n more
+    on the same data make the same forest as 2n trees
+    */
+    pub fn fit_more(&mut self, m: DMatrix, n_tree: usize, control: &Control, monitor: &mut FitMonitor) -> Result<(), Cancelled> {
+        let learners = self.grow(&m, &self.learners, n_tree, control, monitor)?;
+        self.learners.extend(learners);
+        self.conf.n_tree = self.learners.len();
+        Ok(())
+    }
+
+    /*
+    grows n_tree trees after the existing ones. they are built on the pool but reported to the
+    monitor in order, from the calling thread, and when the callback or the early stopping stops
+    the fit the trees reported so far are returned. the oob metric only covers the new trees
+    */
+    fn grow(& self, m: &DMatrix, existing: &[TreeLearn<T, S>], n_tree: usize, control: &Control,
+        monitor: &mut FitMonitor) -> Result<Vec<TreeLearn<T, S>>, Cancelled> {
+
         let seed = self.conf.seed.clone();
-        let rng: StdRng = match seed {
+        let mut rng: StdRng = match seed {
             None => StdRng::from_entropy(),
             Some(v) => SeedableRng::seed_from_u64(v)
         };
 
-        let rng_share = Arc::new(Mutex::new(rng));
-        let mut random = Random::new(rng_share);
+        // every tree draws from its own generator, so the forest does not depend on the scheduling
+        let first = existing.len();
+        let seeds: Vec<u64> = (0..first + n_tree).map(|_| rng.gen()).skip(first).collect();
 
-        let mut learners = Vec::new();
+        let start = Instant::now();
+        let stopped = Control::new();
+        let log_splits = monitor.log_splits;
+        let mut oob = match monitor.needs_oob() {
+            true => Some(OutOfBag::new(m.size())),
+            false => None
+        };
+
+        let mut validation = monitor.validation.map(|v| {
+            let mut score = OutOfBag::new(v.size());
+            for learn in existing.iter() {
+                score.add(v, &learn.flat, &[]);
+            }
+            (v, score)
+        });
+
+        let conf = & self.conf;
+        let pool = self.pool();
+        let mut learners: Vec<TreeLearn<T, S>> = Vec::new();
+
+        thread::scope(|scope| {
+            let (tx, rx) = mpsc::channel::<(usize, TreeLearn<T, S>)>();
+            let stopped = &stopped;
+            let seeds = &seeds;
+            scope.spawn(move || pool.install(|| {
+                seeds.par_iter().enumerate().for_each_with(tx, |tx, (k, seed)| {
+                    if control.is_cancelled() || stopped.is_cancelled() {
+                        return;
+                    }
+
+                    let rng: StdRng = SeedableRng::seed_from_u64(*seed);
+                    let mut random = Random::new(Arc::new(Mutex::new(rng)));
+
+                    let mut learn: TreeLearn<T, S> = TreeLearn::new(conf);
+                    if log_splits {
+                        learn.split_log = Some(Vec::new());
+                    }
+                    learn.fit(m, conf, &mut random);
+                    tx.send((k, learn)).ok();
+                });
+            }));
+
+            let mut pending: Vec<Option<TreeLearn<T, S>>> = (0..seeds.len()).map(|_| None).collect();
+            for (k, learn) in rx.iter() {
+                pending[k] = Some(learn);
+                while let Some(mut learn) = pending.get_mut(learners.len()).and_then(|x| x.take()) {
+                    let oob_metric = oob.as_mut().and_then(|x| {
+                        x.add(m, &learn.flat, &learn.partition.data_ids);
+                        x.mse(m)
+                    });
+
+                    let validation_metric = validation.as_mut().and_then(|(v, x)| {
+                        x.add(v, &learn.flat, &[]);
+                        x.mse(v)
+                    });
+
+                    let info = TreeInfo {
+                        index: first + learners.len(),
+                        depth: learn.flat.depth(),
+                        n_leaves: learn.flat.n_leaves(),
+                        elapsed: start.elapsed(),
+                        oob: oob_metric,
+                        validation: validation_metric,
+                        splits: learn.split_log.take().unwrap_or_default()
+                    };
+                    learners.push(learn);
+
+                    let action = (monitor.callback)(&info);
+                    let plateau = match monitor.early_stopping.as_mut() {
+                        Some(x) => x.update(info.validation.or(info.oob)),
+                        None => Action::Continue
+                    };
+
+                    if action == Action::Stop || plateau == Action::Stop {
+                        stopped.cancel();
+                        break;
+                    }
+                }
+
+                if stopped.is_cancelled() {
+                    break;
+                }
+            }
+        });
 
-        for _ in 0..self.conf.n_tree {
-            let mut learn: TreeLearn<T, S> = TreeLearn::new(& self.conf);
-            learn.fit(&m, & self.conf, &mut random);
-            learners.push(learn);
+        match control.is_cancelled() {
+            true => Err(Cancelled),
+            false => Ok(learners)
         }
+    }
 
-        learners
+    pub fn predict(& self, m: DMatrix) -> Vec<Vec<Float>> {
+        self.predict_with(m, &Control::new()).unwrap()
     }
 
-    fn fit_par_thread(k: usize, conf: Arc<TreeConfig>, m: Arc<DMatrix>, 
-        random: &mut Random) -> Vec<TreeLearn<T, S>> {
+    // the control is checked before each chunk of rows
+    pub fn predict_with(& self, m: DMatrix, control: &Control) -> Result<Vec<Vec<Float>>, Cancelled> {
+        self.average(&m, control, |tree, leaf| tree.leaf_uplift(leaf))
+    }
 
-        let mut learners = Vec::new();
+    /*
+    expected response under the control and every treatment, [row][arm]: the probability of
+    the positive class for a classifier, the mean for a regressor
+    */
+    pub fn predict_outcomes(& self, m: DMatrix) -> Vec<Vec<Float>> {
+        self.average(&m, &Control::new(), |tree, leaf| tree.leaf_outcome(leaf)).unwrap()
+    }
 
-        for _ in 0..k {
-            let mut learn: TreeLearn<T, S> = TreeLearn::new(&(*conf));
-            learn.fit(&(*m), &(*conf), random);
-            learners.push(learn);
+    /*
+    rows are scored in chunks, each chunk walking every tree in order, so the average is
+    the same whatever the number of threads
+    */
+    fn average<F>(& self, m: &DMatrix, control: &Control, score: F) -> Result<Vec<Vec<Float>>, Cancelled>
+        where F: Fn(&FlatTree, usize) -> Vec<Float> + Sync {
+
+        let n = m.size();
+        let n_tree = self.learners.len();
+        let mut avg: Vec<Vec<Float>> = vec![Vec::new(); n];
+        let trees: Vec<&FlatTree> = self.learners.iter().map(|x| &x.flat).collect();
+
+        self.pool().install(|| {
+            avg.par_chunks_mut(PREDICT_CHUNK_SIZE).enumerate().for_each(|(k, chunk)| {
+                if control.is_cancelled() {
+                    return;
+                }
+
+                for (i, sum) in chunk.iter_mut().enumerate() {
+                    let row = k * PREDICT_CHUNK_SIZE + i;
+                    for tree in trees.iter() {
+                        let value = score(tree, tree.route(m, row));
+                        *sum = match sum.len() {
+                            0 => value,
+                            _ => value.iter().zip(sum.iter()).map(|(a, b)| a + b).collect()
+                        };
+                    }
+
+                    for x in sum.iter_mut() {
+                        *x /= n_tree as Float;
+                    }
+                }
+            });
+        });
+
+        match control.is_cancelled() {
+            true => Err(Cancelled),
+            false => Ok(avg)
         }
+    }
 
-        learners
+    /*
+    average uplift of the first 1..n_tree trees, [stage][row][arm]. the last stage is predict
+    */
+    pub fn predict_staged(& self, m: DMatrix) -> Vec<Vec<Vec<Float>>> {
+        by_tree(self.predict_trees(&m, true), self.learners.len())
     }
 
-    fn fit_par(&mut self, m: DMatrix) -> Vec<TreeLearn<T, S>> {
-        let n_tree = self.conf.n_tree;
-        let n_thread = self.conf.n_thread;
+    // uplift of every tree, [tree][row][arm]
+    pub fn predict_per_tree(& self, m: DMatrix) -> Vec<Vec<Vec<Float>>> {
+        by_tree(self.predict_trees(&m, false), self.learners.len())
+    }
 
-        let mut jobs: Vec<usize> = Vec::new();
+    /*
+    [row][tree][arm] in one walk of the trees per row, holding the running average when staged.
+    the sums are taken in the order predict takes them
+    */
+    fn predict_trees(& self, m: &DMatrix, staged: bool) -> Vec<Vec<Vec<Float>>> {
+        let n = m.size();
+        let mut scores: Vec<Vec<Vec<Float>>> = vec![Vec::new(); n];
+        let trees: Vec<&FlatTree> = self.learners.iter().map(|x| &x.flat).collect();
+
+        self.pool().install(|| {
+            scores.par_chunks_mut(PREDICT_CHUNK_SIZE).enumerate().for_each(|(k, chunk)| {
+                for (i, score) in chunk.iter_mut().enumerate() {
+                    let row = k * PREDICT_CHUNK_SIZE + i;
+                    let mut sum: Vec<Float> = Vec::new();
+                    for (t, tree) in trees.iter().enumerate() {
+                        let uplift = tree.leaf_uplift(tree.route(m, row));
+                        if !staged {
+                            score.push(uplift);
+                            continue;
+                        }
+
+                        sum = match sum.len() {
+                            0 => uplift,
+                            _ => uplift.iter().zip(sum.iter()).map(|(a, b)| a + b).collect()
+                        };
+                        score.push(sum.iter().map(|x| x / (t + 1) as Float).collect());
+                    }
+                }
+            });
+        });
 
-        if n_tree < n_thread {
-            jobs.extend(vec![1; n_tree]);
-        } else {
-            let q: usize = n_tree / n_thread;
-            let r: usize = n_tree % n_thread;
-    
-            let mut jobs_ = vec![q; n_thread];
-            for i in 0..r {
-                jobs_[i] += 1;
-            }
-            jobs.extend(jobs_)
-        }
+        scores
+    }
 
-        let mut learners = Vec::new();
+    // id of the leaf node every row falls in, [row][tree]
+    pub fn apply(& self, m: DMatrix) -> Vec<Vec<usize>> {
+        self.leaves(&m)
+    }
 
-        let seed = self.conf.seed.clone();
-        let rng: StdRng = match seed {
-            None => StdRng::from_entropy(),
-            Some(v) => SeedableRng::seed_from_u64(v)
-        };
+    fn leaves(& self, m: &DMatrix) -> Vec<Vec<usize>> {
+        let mut leaves: Vec<Vec<usize>> = vec![Vec::new(); m.size()];
+        let trees: Vec<&FlatTree> = self.learners.iter().map(|x| &x.flat).collect();
 
-        let m_share = Arc::new(m);
-        let conf = self.conf.clone();
-        let conf_share = Arc::new(conf);
-        let rng_share = Arc::new(Mutex::new(rng));
-
-        let mut handles = Vec::new();
-        for k in jobs.iter() {
-            let k_c = k.clone();
-            let d = Arc::clone(&m_share);
-            let c = Arc::clone(&conf_share);
-            let r_c = Arc::clone(&rng_share);
-            let mut r = Random::new(r_c);
-
-            let handle = thread::spawn(move || {
-                RandomForest::fit_par_thread(k_c, c, d, &mut r)
+        self.pool().install(|| {
+            leaves.par_chunks_mut(PREDICT_CHUNK_SIZE).enumerate().for_each(|(k, chunk)| {
+                for (i, leaf) in chunk.iter_mut().enumerate() {
+                    let row = k * PREDICT_CHUNK_SIZE + i;
+                    leaf.extend(trees.iter().map(|tree| tree.leaf_nodes[tree.route(m, row)]));
+                }
             });
+        });
 
-            handles.push(handle);
-        }
+        leaves
+    }
 
-        for h in handles {
-            let result = h.join().unwrap();
-            learners.extend(result);
+    /*
+    weight of every row of train in the neighbourhood of every row of query: each tree gives
+    1 / leaf size to the rows of train sharing the leaf of the query row, averaged over the trees
+    whose leaf holds rows of train, so the weights of a query row add up to 1.
+    sparse, as (row of train, weight) sorted by row
+    */
+    pub fn forest_weights(& self, train: &DMatrix, query: &DMatrix) -> Vec<Vec<(usize, Float)>> {
+        let n_tree = self.learners.len();
+        let mut members: Vec<HashMap<usize, Vec<usize>>> = vec![HashMap::new(); n_tree];
+        for (i, leaf) in self.leaves(train).iter().enumerate() {
+            for (t, node) in leaf.iter().enumerate() {
+                members[t].entry(*node).or_default().push(i);
+            }
         }
 
-        learners
+        let leaves = self.leaves(query);
+        self.pool().install(|| {
+            leaves.par_iter().map(|leaf| {
+                let mut weights: HashMap<usize, Float> = HashMap::new();
+                let mut n_used = 0;
+                for (t, node) in leaf.iter().enumerate() {
+                    let rows = match members[t].get(node) {
+                        Some(rows) => rows,
+                        None => continue
+                    };
+
+                    n_used += 1;
+                    for i in rows.iter() {
+                        *weights.entry(*i).or_insert(0.0) += 1.0 / rows.len() as Float;
+                    }
+                }
+
+                let mut weights: Vec<(usize, Float)> = weights.into_iter()
+                    .map(|(i, w)| (i, w / n_used as Float))
+                    .collect();
+                weights.sort_by_key(|x| x.0);
+                weights
+            }).collect()
+        })
     }
 
-    pub fn predict_seq(&mut self, m: DMatrix) -> Vec<Vec<Float>> {
-        let mut avg: Option<Matrix<Float>> = None;
-
-        for learn in self.learners.iter_mut() {
-            let score = learn.predict(&m);
-            let arr: Matrix<Float> = Matrix::new(& score);
+    // decision path of a row in every tree, in original feature units, with per-feature contributions
+    pub fn explain(& self, m: &DMatrix, row: usize) -> Explanation {
+        let trees: Vec<&T> = self.learners.iter().map(|x| &x.tree).collect();
+        explain(&trees, &self.loader, m, row)
+    }
 
-            if avg.is_none() {
-                avg = Some(arr);
-                continue;
-            }
+    /*
+    TreeSHAP attributions of the uplift of every row to every feature, [row][feature][uplift],
+    averaged over the trees. with expected_value they add up to predict
+    */
+    pub fn shap_values(& self, m: DMatrix) -> Vec<Vec<Vec<Float>>> {
+        let trees: Vec<ShapTree> = self.learners.iter().map(|x| ShapTree::new(&x.tree)).collect();
+        let n = m.size();
+        let chunks: Vec<Range<usize>> = (0..n).step_by(PREDICT_CHUNK_SIZE)
+            .map(|x| x..cmp::min(x + PREDICT_CHUNK_SIZE, n))
+            .collect();
+
+        let values: Vec<Vec<Vec<Vec<Float>>>> = self.pool().install(|| {
+            chunks.into_par_iter().map(|rows| shap_values(&trees, &m, rows)).collect()
+        });
+
+        values.into_iter().flatten().collect()
+    }
 
-            let arr = arr.add(& avg.unwrap());
-            avg = Some(arr);
+    // uplift averaged over the training rows, the base of the attributions of shap_values
+    pub fn expected_value(& self) -> Vec<Float> {
+        let mut expected: Vec<f64> = Vec::new();
+        for learn in self.learners.iter() {
+            let value = ShapTree::new(&learn.tree).expected_value();
+            expected = match expected.len() {
+                0 => value,
+                _ => value.iter().zip(expected.iter()).map(|(a, b)| a + b).collect()
+            };
         }
 
-        let avg = avg.unwrap();
-        let avg = avg.divide_scalar(self.learners.len() as Float);
-        avg.get_data().clone()
+        expected.iter().map(|x| (x / self.learners.len() as f64) as Float).collect()
     }
 
-    fn split_job_predict(& self, n_tree: usize, n_thread: usize) -> Vec<Vec<usize>> {
-        if n_tree < n_thread {
-            let v: Vec<usize> = (0..n_tree).collect();
-            let mut vs = Vec::new();
-            for i in v {
-                vs.push(vec![i]);
-            }
+    /*
+    drop of the metric on m, held out rows, when each feature is shuffled, over n_repeat
+    permutations drawn from the seed of the config
+    */
+    pub fn permutation_importance(& self, m: &DMatrix, metric: UpliftMetric, n_repeat: usize) -> Vec<Importance> {
+        let trees: Vec<&FlatTree> = self.learners.iter().map(|x| &x.flat).collect();
+        let seed = self.conf.seed;
+        self.pool().install(|| permutation_importance(&trees, m, metric, n_repeat, seed))
+    }
 
-            return vs;
-        }
+    // partial dependence of the uplift on the features of m, by index, over the bins of the loader
+    pub fn partial_dependence(& self, m: &DMatrix, features: &[usize], individual: bool) -> PartialDependence {
+        let trees: Vec<&FlatTree> = self.learners.iter().map(|x| &x.flat).collect();
+        let loader = &self.loader;
+        self.pool().install(|| partial_dependence(&trees, loader, m, features, individual, PREDICT_CHUNK_SIZE))
+    }
 
-        let q: usize = n_tree / n_thread;
-        let r: usize = n_tree % n_thread;
+    // the leaves of the forest as ranked rules on the original feature values
+    pub fn rules(& self, options: &RuleOptions) -> Vec<Rule> {
+        let trees: Vec<&T> = self.learners.iter().map(|x| &x.tree).collect();
+        extract_rules(&trees, &self.loader, options)
+    }
 
-        let mut vs = Vec::new();
-        for i in 0..n_thread {
-            let start = match i {
-                i if i < r => i * (q + 1),
-                i => r * (q + 1) + (i - r) * q
-            };
+    /*
+    a single tree fitted to the uplift of the forest on m, the reference rows, as a model of its
+    own that saves and loads like a regression forest, with its in-sample R² against the forest,
+    on the rows of m it was fitted to. its leaves hold the outcomes of the forest under control
+    */
+    pub fn distill(& self, m: &DMatrix, max_depth: usize, min_samples_leaf: usize)
+        -> (RandomForest<RegressionTree, RegressionStrategy>, Vec<Float>) {
+
+        let control = Control::new();
+        let target = self.average(m, &control, |tree, leaf| tree.leaf_uplift(leaf)).unwrap();
+        let outcomes = self.average(m, &control, |tree, leaf| tree.leaf_outcome(leaf)).unwrap();
+        let base: Vec<Float> = outcomes.iter().map(|x| x[0]).collect();
+
+        let mut conf = self.conf.clone();
+        conf.n_tree = 1;
+        conf.n_tree_configured = None;
+        conf.max_depth = max_depth;
+        conf.min_samples_leaf = min_samples_leaf;
+
+        let mut learn: TreeLearn<RegressionTree, RegressionStrategy> = TreeLearn::new(&conf);
+        learn.tree = fit_surrogate(m, &target, &base, max_depth, min_samples_leaf);
+        learn.compile();
+
+        let surrogate = RandomForest { conf, loader: self.loader.clone(), learners: vec![learn], pool: Mutex::new(None) };
+        let score = surrogate.average(m, &control, |tree, leaf| tree.leaf_uplift(leaf)).unwrap();
+        let in_sample = r_squared(&target, &score, &m.weights);
+        (surrogate, in_sample)
+    }
 
-            let size = match i {
-                i if i < r => q + 1,
-                _ => q
-            };
+    // tree index as indented text with decoded conditions, None past the last tree
+    pub fn tree_to_text(& self, index: usize) -> Option<String> {
+        self.learners.get(index).map(|x| to_text(&x.tree, &self.loader))
+    }
 
-            let end = start + size;
-            let v: Vec<usize> = (start..end).collect();
-            vs.push(v);
-        }
+    // tree index as a graphviz digraph, None past the last tree
+    pub fn tree_to_dot(& self, index: usize) -> Option<String> {
+        self.learners.get(index).map(|x| to_dot(&x.tree, &self.loader))
+    }
 
-        vs
+    /*
+    a sql query scoring the rows of table with the forest, an uplift column per treatment next to
+    the index columns of the config
+    */
+    pub fn to_sql(& self, table: &str) -> String {
+        let trees: Vec<&T> = self.learners.iter().map(|x| &x.tree).collect();
+        forest_to_sql(&trees, &self.loader, table, &self.conf.index_cols)
     }
 
-    fn predict_par_thread(learners: &mut Vec<TreeLearn<T, S>>, m: Arc<DMatrix>) -> Vec<Vec<Float>> {
-        let mut avg: Option<Matrix<Float>> = None;
+    // row holds the binned features in the order of the model features
+    pub fn predict_row(& self, row: &[Option<Int>]) -> Vec<Float> {
+        let mut avg: Vec<Float> = Vec::new();
+        for learn in self.learners.iter() {
+            let score = learn.predict_row(row);
+            avg = match avg.len() {
+                0 => score,
+                _ => score.iter().zip(avg.iter()).map(|(a, b)| a + b).collect()
+            };
+        }
 
-        for learn in learners.iter_mut() {
-            let score = (*learn).predict(&m);
-            let arr: Matrix<Float> = Matrix::new(& score);
+        avg.iter().map(|x| x / self.learners.len() as Float).collect()
+    }
 
-            if avg.is_none() {
-                avg = Some(arr);
-                continue;
+    /*
+    trees, and the features of large nodes, are scheduled on a pool of n_thread threads, built on
+    first use and again only when n_thread changes
+    */
+    fn pool(& self) -> Arc<ThreadPool> {
+        let n_thread = cmp::max(self.conf.n_thread, 1);
+        let mut pool = self.pool.lock().unwrap();
+        match pool.as_ref() {
+            Some((n, x)) if *n == n_thread => x.clone(),
+            _ => {
+                let x = Arc::new(ThreadPoolBuilder::new().num_threads(n_thread).build().unwrap());
+                *pool = Some((n_thread, x.clone()));
+                x
             }
-
-            let arr = arr.add(& avg.unwrap());
-            avg = Some(arr);
         }
-
-        avg.unwrap().get_data().clone()
     }
+}
 
-    pub fn predict_par(&mut self, m: DMatrix) -> Vec<Vec<Float>> {
-        let jobs: Vec<Vec<usize>> = self.split_job_predict(self.conf.n_tree, self.conf.n_thread);
+// [row][tree][arm] to [tree][row][arm]
+fn by_tree(scores: Vec<Vec<Vec<Float>>>, n_tree: usize) -> Vec<Vec<Vec<Float>>> {
+    let mut trees: Vec<Vec<Vec<Float>>> = (0..n_tree).map(|_| Vec::with_capacity(scores.len())).collect();
+    for row in scores {
+        for (t, score) in row.into_iter().enumerate() {
+            trees[t].push(score);
+        }
+    }
 
-        let m_share = Arc::new(m);
+    trees
+}
 
-        let mut handles = Vec::new();
-        for k in jobs {
-            let mut learners: Vec<TreeLearn<T, S>> = Vec::new();
-            for i in k.iter() {
-                let learner: TreeLearn<T, S> = self.learners.get(*i).unwrap().clone();
-                learners.push(learner);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture;
+    use crate::tree::ClassificationTree;
+    use crate::kl::KLStrategy;
+
+    // a control cancelled by the callback after the second new tree
+    fn cancelling(control: &Control) -> FitMonitor<'_> {
+        FitMonitor::new(move |info: &TreeInfo| {
+            if info.index % 10 == 1 {
+                control.cancel();
             }
+            Action::Continue
+        })
+    }
 
-            let d = Arc::clone(&m_share);
+    #[test]
+    fn test_cancelled_fit() {
+        let headers = fixture::headers();
+        let rows = fixture::to_float(&fixture::data(2000, 1));
+        let mut rf: RandomForest<ClassificationTree, KLStrategy> = RandomForest::new(fixture::conf("y", 10));
+        let m = rf.loader.from_memory(&headers, &Vec::new(), &rows);
+        rf.fit(m);
+        let before = rf.to_string();
+
+        let control = Control::new();
+        let m = rf.loader.from_memory(&headers, &Vec::new(), &rows);
+        assert!(rf.fit_monitored(m, &control, &mut cancelling(&control)).is_err());
+        assert_eq!(rf.to_string(), before);
+
+        let control = Control::new();
+        let m = rf.loader.from_memory(&headers, &Vec::new(), &rows);
+        assert!(rf.fit_more(m, 10, &control, &mut cancelling(&control)).is_err());
+        assert_eq!(rf.to_string(), before);
+    }
 
-            let handle = thread::spawn(move || {
-                RandomForest::predict_par_thread(&mut learners, d)
-            });
+    #[test]
+    fn test_early_stopped_size() {
+        let headers = fixture::headers();
+        let rows = fixture::to_float(&fixture::data(2000, 1));
+        let mut rf: RandomForest<ClassificationTree, KLStrategy> = RandomForest::new(fixture::conf("y", 10));
+        let m = rf.loader.from_memory(&headers, &Vec::new(), &rows);
+        let mut monitor = FitMonitor::new(|info: &TreeInfo| match info.index {
+            x if x >= 3 => Action::Stop,
+            _ => Action::Continue
+        });
+        rf.fit_monitored(m, &Control::new(), &mut monitor).unwrap();
+        assert_eq!((rf.learners.len(), rf.conf.n_tree, rf.conf.n_tree_configured), (4, 4, Some(10)));
+
+        // the body alone, as files without a header are, gives the same trees
+        let loaded: RandomForest<ClassificationTree, KLStrategy> = RandomForest::from_string(&rf.to_body()).unwrap();
+        assert_eq!(loaded.learners.len(), 4);
+        assert_eq!(loaded.to_body(), rf.to_body());
+
+        let m = rf.loader.from_memory(&headers, &Vec::new(), &rows);
+        rf.fit_more(m, 3, &Control::new(), &mut FitMonitor::default()).unwrap();
+        assert_eq!((rf.learners.len(), rf.conf.n_tree), (7, 7));
+
+        let m = rf.loader.from_memory(&headers, &Vec::new(), &rows);
+        rf.fit(m);
+        assert_eq!((rf.learners.len(), rf.conf.n_tree), (10, 10));
+    }
 
-            handles.push(handle);
+    #[test]
+    fn test_distilled_outcomes() {
+        let headers = fixture::headers();
+        let rows = fixture::to_float(&fixture::data(2000, 1));
+        let mut rf: RandomForest<ClassificationTree, KLStrategy> = RandomForest::new(fixture::conf("y", 10));
+        let m = rf.loader.from_memory(&headers, &Vec::new(), &rows);
+        rf.fit(m);
+
+        let m = rf.loader.from_memory(&headers, &Vec::new(), &rows);
+        let (mut surrogate, in_sample) = rf.distill(&m, 3, 100);
+        assert!(in_sample[0] > 0.0 && in_sample[0] <= 1.0);
+
+        // every leaf averages the outcomes of the forest on its rows, so the averages over the rows agree
+        let mean = |x: &[Vec<Float>], arm: usize| x.iter().map(|r| r[arm]).sum::<Float>() / x.len() as Float;
+        let expected = rf.predict_outcomes(m);
+        let m = surrogate.loader.from_memory(&headers, &Vec::new(), &rows);
+        let outcomes = surrogate.predict_outcomes(m);
+        let m = surrogate.loader.from_memory(&headers, &Vec::new(), &rows);
+        let uplift = surrogate.predict(m);
+        for arm in 0..2 {
+            assert!((mean(&outcomes, arm) - mean(&expected, arm)).abs() < 1e-4);
         }
-
-        let mut avg: Option<Matrix<Float>> = None;
-
-        for h in handles {
-            let score = h.join().unwrap();
-            let arr: Matrix<Float> = Matrix::new(& score);
-
-            if avg.is_none() {
-                avg = Some(arr);
-                continue;
-            }
-
-            let arr = arr.add(& avg.unwrap());
-            avg = Some(arr);
+        for (o, u) in outcomes.iter().zip(uplift.iter()) {
+            assert!((o[1] - o[0] - u[0]).abs() < 1e-5);
         }
+    }
 
-        let avg = avg.unwrap();
-        let avg = avg.divide_scalar(self.learners.len() as Float);
-        avg.get_data().clone()
+    /*
+    testdata/rf_0.1.txt is a classifier saved by 0.1.x, without a header, fitted on fixture::data(3000, 1)
+    with max_depth 4 and 3 trees. rf_0.1_predictions.json holds what 0.1.x predicted on fixture::data(50, 2)
+    */
+    #[test]
+    fn test_headerless_model() {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/");
+        let mut rf: RandomForest<ClassificationTree, KLStrategy> = RandomForest::load(&format!("{}rf_0.1.txt", dir)).unwrap();
+        let expected: Vec<Vec<Float>> = serde_json::from_str(
+            &std::fs::read_to_string(format!("{}rf_0.1_predictions.json", dir)).unwrap()).unwrap();
+
+        let m = rf.loader.from_memory(&fixture::headers(), &Vec::new(), &fixture::to_float(&fixture::data(50, 2)));
+        assert_eq!(rf.predict(m), expected);
     }
 }
@@ -0,0 +1,382 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::cmp::Ordering;
+use serde::Serialize;
+
+use crate::config::Float;
+use crate::data_loader::DataLoader;
+use crate::split_info::SplitInfoTrait;
+use crate::tree::Tree;
+use crate::explain::{Condition, node_uplift, n_bins, decode_bins};
+
+/*
+one feature of a rule: the values it takes, None when only missing values satisfy it, and whether
+missing values satisfy it as well
+*/
+#[derive(Clone, Debug, Serialize)]
+pub struct Clause {
+    pub feature: String,
+    pub values: Option<Condition>,
+    pub missing: bool,
+}
+
+/*
+a segment of rows and its uplift, from one leaf or several merged. support is the number of
+training rows in it, summed over the arms of the leaf summaries and averaged over the trees, 0 for
+regression models saved without counts
+*/
+#[derive(Clone, Debug, Serialize)]
+pub struct Rule {
+    pub text: String,
+    pub clauses: Vec<Clause>,
+    pub uplift: Vec<Float>,
+    pub support: Float,
+    pub n_leaves: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct RuleOptions {
+    // rules with less support are dropped
+    pub min_support: Float,
+    // neighbouring rules whose uplifts differ by no more than this are merged, none to keep them apart
+    pub tolerance: Option<Float>,
+    pub max_rules: Option<usize>,
+    // the treatment the rules are ranked by, 0 for the first one
+    pub arm: usize,
+}
+
+impl Default for RuleOptions {
+    fn default() -> RuleOptions {
+        RuleOptions { min_support: 0.0, tolerance: None, max_rules: None, arm: 0 }
+    }
+}
+
+// the bins of a feature a segment admits, and whether it admits missing values
+type Constraints = BTreeMap<usize, (Vec<bool>, bool)>;
+
+#[derive(Clone)]
+struct Segment {
+    constraints: Constraints,
+    uplift: Vec<Float>,
+    support: Float,
+    n_leaves: usize,
+    // the trees of the leaves
+    trees: BTreeSet<usize>,
+}
+
+impl Segment {
+
+    // support weighted uplift of two segments, the leaves counting the same when there are no counts
+    fn absorb(&mut self, other: &Segment) {
+        let (a, b) = match self.support + other.support {
+            x if x > 0.0 => (self.support / x, other.support / x),
+            _ => {
+                let n = (self.n_leaves + other.n_leaves) as Float;
+                (self.n_leaves as Float / n, other.n_leaves as Float / n)
+            }
+        };
+
+        self.uplift = self.uplift.iter().zip(other.uplift.iter()).map(|(x, y)| a * x + b * y).collect();
+        self.n_leaves += other.n_leaves;
+        self.trees.extend(other.trees.iter().copied());
+    }
+}
+
+// the segment of every leaf of a tree, from the splits on the path to it
+fn leaf_segments<T: Tree>(tree: &T, loader: &DataLoader) -> Vec<Segment> {
+    let left_children = tree.left_children();
+    let right_children = tree.right_children();
+    let splits = tree.splits();
+
+    let mut segments: Vec<Segment> = Vec::new();
+    let mut stack: Vec<(usize, Constraints)> = vec![(0, BTreeMap::new())];
+    while let Some((n, constraints)) = stack.pop() {
+        let split_info = splits[n].as_ref().unwrap();
+        let (l, r) = match (left_children[n], right_children[n]) {
+            (Some(l), Some(r)) => (l, r),
+            _ => {
+                let summary = split_info.get_summary();
+                let support = match summary.iter().all(|x| x.len() > 1) {
+                    true => summary.iter().map(|x| x[1]).sum(),
+                    false => 0.0
+                };
+                segments.push(Segment { constraints, uplift: node_uplift(split_info), support, n_leaves: 1, trees: BTreeSet::new() });
+                continue;
+            }
+        };
+
+        let feature_id = split_info.get_feature_id();
+        let iscat = split_info.get_iscat();
+        let value = split_info.get_value().unwrap();
+        let missing_left = split_info.get_missing_left();
+        let name = &loader.features[feature_id];
+
+        for (child, left) in [(r, false), (l, true)] {
+            let mut constraints = constraints.clone();
+            let (bins, missing) = constraints.entry(feature_id)
+                .or_insert_with(|| (vec![true; n_bins(loader, name)], true));
+
+            for (b, admitted) in bins.iter_mut().enumerate() {
+                let goes_left = match iscat {
+                    true => value.iter().any(|x| *x as usize == b),
+                    false => b <= value[0] as usize
+                };
+                *admitted = *admitted && goes_left == left;
+            }
+            *missing = *missing && missing_left == left;
+            stack.push((child, constraints));
+        }
+    }
+
+    // constraints every row meets say nothing
+    for segment in segments.iter_mut() {
+        segment.constraints.retain(|_, (bins, missing)| !(*missing && bins.iter().all(|x| *x)));
+    }
+    segments
+}
+
+// whether the admitted bins of a continuous feature form one range
+fn contiguous(bins: &[bool]) -> bool {
+    match (bins.iter().position(|x| *x), bins.iter().rposition(|x| *x)) {
+        (Some(s), Some(e)) => bins[s..e + 1].iter().all(|x| *x),
+        _ => true
+    }
+}
+
+// whether every row the leaf admits is admitted by the segment
+fn contains(segment: &Constraints, leaf: &Constraints) -> bool {
+    segment.iter().all(|(f, (bins, missing))| match leaf.get(f) {
+        Some((b, m)) => b.iter().zip(bins.iter()).all(|(x, y)| !*x || *y) && (!*m || *missing),
+        None => false
+    })
+}
+
+/*
+one sweep merging the segments that differ in feature f only and whose uplifts are within
+tolerance: for a continuous feature in the order of their first bin, as long as the union of the
+bins is a range, and for a discrete one in the order of their uplift for arm
+*/
+fn merge_on(segments: Vec<Segment>, f: usize, discrete: bool, tolerance: Float, arm: usize) -> Vec<Segment> {
+    let mut groups: HashMap<Constraints, Vec<usize>> = HashMap::new();
+    for (i, segment) in segments.iter().enumerate() {
+        if segment.constraints.contains_key(&f) {
+            let mut rest = segment.constraints.clone();
+            rest.remove(&f);
+            groups.entry(rest).or_default().push(i);
+        }
+    }
+
+    let mut segments: Vec<Option<Segment>> = segments.into_iter().map(Some).collect();
+    for mut members in groups.into_values() {
+        {
+            let get = |i: &usize| segments[*i].as_ref().unwrap();
+            match discrete {
+                true => members.sort_by(|a, b| {
+                    get(a).uplift.get(arm).partial_cmp(&get(b).uplift.get(arm)).unwrap_or(Ordering::Equal)
+                }),
+                false => members.sort_by_key(|i| get(i).constraints[&f].0.iter().position(|x| *x).unwrap_or(usize::MAX))
+            }
+        }
+
+        let mut current = members[0];
+        for i in members.into_iter().skip(1) {
+            let (a, b) = (segments[current].as_ref().unwrap(), segments[i].as_ref().unwrap());
+            let close = a.uplift.iter().zip(b.uplift.iter()).all(|(x, y)| (x - y).abs() <= tolerance);
+            let union: Vec<bool> = a.constraints[&f].0.iter().zip(b.constraints[&f].0.iter()).map(|(x, y)| *x || *y).collect();
+            if !close || !(discrete || contiguous(&union)) {
+                current = i;
+                continue;
+            }
+
+            let other = segments[i].take().unwrap();
+            let segment = segments[current].as_mut().unwrap();
+            segment.absorb(&other);
+            segment.support += other.support;
+            let (bins, missing) = segment.constraints.get_mut(&f).unwrap();
+            *bins = union;
+            *missing = *missing || other.constraints[&f].1;
+        }
+    }
+
+    segments.into_iter().flatten().map(|mut x| {
+        x.constraints.retain(|_, (bins, missing)| !(*missing && bins.iter().all(|x| *x)));
+        x
+    }).collect()
+}
+
+// segments with the same constraints are kept once, their uplift weighted by support
+fn deduplicate(segments: Vec<Segment>) -> Vec<Segment> {
+    let mut index: HashMap<Constraints, usize> = HashMap::new();
+    let mut unique: Vec<Segment> = Vec::new();
+    for segment in segments {
+        match index.get(&segment.constraints) {
+            Some(k) => unique[*k].absorb(&segment),
+            None => {
+                index.insert(segment.constraints.clone(), unique.len());
+                unique.push(segment);
+            }
+        }
+    }
+    unique
+}
+
+/*
+the segment on the original feature values. a clause whose bins decode to no value keeps only the
+missing values, and the segment is None when one keeps no value at all
+*/
+fn to_rule(segment: &Segment, loader: &DataLoader) -> Option<Rule> {
+    let clauses: Vec<Clause> = segment.constraints.iter().map(|(f, (bins, missing))| {
+        let feature = loader.features[*f].clone();
+        let admitted: Vec<usize> = (0..bins.len()).filter(|b| bins[*b]).collect();
+        let values = match admitted.len() {
+            0 => None,
+            _ => Some(decode_bins(loader, &feature, &admitted)).filter(|x| !x.is_empty())
+        };
+        Clause { feature, values, missing: *missing }
+    }).collect();
+    if clauses.iter().any(|c| c.values.is_none() && !c.missing) {
+        return None;
+    }
+
+    let text = match clauses.len() {
+        0 => "all".to_string(),
+        _ => clauses.iter().map(|c| match (&c.values, c.missing) {
+            (None, _) => Condition::Missing.describe(&c.feature),
+            (Some(x), false) => x.describe(&c.feature),
+            (Some(x), true) => format!("({} or {})", x.describe(&c.feature), Condition::Missing.describe(&c.feature))
+        }).collect::<Vec<String>>().join(" and ")
+    };
+
+    Some(Rule { text, clauses, uplift: segment.uplift.clone(), support: segment.support, n_leaves: segment.n_leaves })
+}
+
+/*
+the leaves of the trees as rules on the original feature values, ranked by the uplift of the arm
+of the options and then by support. the same segment found in several trees is kept once, with its
+uplift weighted by support. with a tolerance, segments differing in one feature whose uplifts are
+that close are merged, in one sweep per feature. the trees cover the same rows, so the support of
+a segment is that of the leaves it contains, averaged over the trees it comes from
+*/
+pub fn extract_rules<T: Tree>(trees: &[&T], loader: &DataLoader, options: &RuleOptions) -> Vec<Rule> {
+    let mut leaves: Vec<Vec<(Constraints, Float)>> = Vec::new();
+    let mut segments: Vec<Segment> = Vec::new();
+    for (t, tree) in trees.iter().enumerate() {
+        let found = leaf_segments(*tree, loader);
+        leaves.push(found.iter().map(|x| (x.constraints.clone(), x.support)).collect());
+        segments.extend(found.into_iter().map(|mut x| {
+            x.trees.insert(t);
+            x
+        }));
+    }
+    let mut segments = deduplicate(segments);
+
+    if let Some(tolerance) = options.tolerance {
+        let features: BTreeSet<usize> = segments.iter().flat_map(|x| x.constraints.keys().copied()).collect();
+        for f in features {
+            let discrete = loader.bins_disc.contains_key(&loader.features[f]);
+            segments = merge_on(segments, f, discrete, tolerance, options.arm);
+        }
+        segments = deduplicate(segments);
+    }
+
+    for segment in segments.iter_mut() {
+        let total: Float = segment.trees.iter()
+            .flat_map(|t| leaves[*t].iter())
+            .filter(|(c, _)| contains(&segment.constraints, c))
+            .map(|(_, s)| *s)
+            .sum();
+        segment.support = total / segment.trees.len() as Float;
+    }
+
+    let arm = options.arm;
+    let mut rules: Vec<Rule> = segments.iter()
+        .filter(|x| x.support >= options.min_support)
+        .filter_map(|x| to_rule(x, loader))
+        .collect();
+
+    rules.sort_by(|a, b| {
+        let by_uplift = b.uplift.get(arm).partial_cmp(&a.uplift.get(arm)).unwrap_or(Ordering::Equal);
+        by_uplift.then(b.support.partial_cmp(&a.support).unwrap_or(Ordering::Equal))
+    });
+    if let Some(n) = options.max_rules {
+        rules.truncate(n);
+    }
+
+    rules
+}
+
+// one line per rule, with the uplift of every treatment against the control
+pub fn to_csv(rules: &[Rule]) -> String {
+    let n_uplift = rules.first().map(|x| x.uplift.len()).unwrap_or(1);
+    let mut header: Vec<String> = vec!["rank".to_string(), "rule".to_string()];
+    for k in 1..n_uplift + 1 {
+        header.push(match n_uplift {
+            1 => "uplift".to_string(),
+            _ => format!("uplift_{}", k)
+        });
+    }
+    header.push("support".to_string());
+    header.push("n_leaves".to_string());
+
+    let mut lines: Vec<String> = vec![header.join(",")];
+    for (k, rule) in rules.iter().enumerate() {
+        let mut fields: Vec<String> = vec![(k + 1).to_string(), format!("\"{}\"", rule.text.replace('"', "\"\""))];
+        fields.extend(rule.uplift.iter().map(|x| x.to_string()));
+        fields.push(rule.support.to_string());
+        fields.push(rule.n_leaves.to_string());
+        lines.push(fields.join(","));
+    }
+
+    lines.join("\n") + "\n"
+}
+
+pub fn to_json(rules: &[Rule]) -> String {
+    serde_json::to_string_pretty(rules).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture;
+    use crate::rf::RandomForest;
+    use crate::tree::ClassificationTree;
+    use crate::kl::KLStrategy;
+
+    #[test]
+    fn test_merged_support() {
+        let headers = fixture::headers();
+        let rows = fixture::to_float(&fixture::data(2000, 1));
+        let mut rf: RandomForest<ClassificationTree, KLStrategy> = RandomForest::new(fixture::conf("y", 10));
+        let m = rf.loader.from_memory(&headers, &Vec::new(), &rows);
+        rf.fit(m);
+
+        // the leaves of each tree cover the rows once
+        let exact = rf.rules(&RuleOptions::default());
+        assert!(exact.iter().all(|x| x.support <= 2000.0));
+
+        // merging every segment it can, supports still count each row once per tree
+        let merged = rf.rules(&RuleOptions { tolerance: Some(1.0), ..Default::default() });
+        assert!(merged.len() < exact.len());
+        assert!(merged.iter().all(|x| x.support > 0.0 && x.support <= 2000.0));
+        assert!(merged.iter().any(|x| x.n_leaves > 10));
+    }
+
+    #[test]
+    fn test_uplift_encoded_clauses() {
+        let headers = fixture::headers();
+        let rows = fixture::to_float(&fixture::data(2000, 2));
+        let mut conf = fixture::conf("y", 10);
+        conf.cat_cols = Vec::new();
+        conf.encode_cols = vec!["c2".to_string()];
+        let mut rf: RandomForest<ClassificationTree, KLStrategy> = RandomForest::new(conf);
+        let m = rf.loader.from_memory(&headers, &Vec::new(), &rows);
+        rf.fit(m);
+
+        // training binned out of fold scores, which no category may have once fitted
+        let rules = rf.rules(&RuleOptions::default());
+        assert!(!rules.is_empty());
+        for rule in rules.iter() {
+            assert!(rule.clauses.iter().all(|c| c.missing || c.values.as_ref().is_some_and(|x| !x.is_empty())));
+            assert!(!rule.text.contains("{}"));
+        }
+    }
+}
@@ -0,0 +1,228 @@
+use crate::config::{Int, Float};
+use crate::dmatrix::DMatrix;
+use crate::split_info::SplitInfoTrait;
+use crate::tree::Tree;
+use crate::explain::node_uplift;
+
+/*
+a tree prepared for TreeSHAP: the uplift of every node and its cover, the weighted number of
+training rows that reached it. cover is the sum of the counts of the arms in the node summary,
+and for models saved without counts each child is taken to hold half of its parent
+*/
+pub struct ShapTree {
+    pub left_children: Vec<Option<usize>>,
+    pub right_children: Vec<Option<usize>>,
+    pub feature: Vec<usize>,
+    pub iscat: Vec<bool>,
+    pub value: Vec<Vec<Int>>,
+    pub missing_left: Vec<bool>,
+    pub cover: Vec<f64>,
+    pub uplift: Vec<Vec<f64>>,
+}
+
+// one feature on the path from the root, with the fractions of rows that follow it
+#[derive(Clone, Copy)]
+struct PathElement {
+    feature: usize,
+    zero: f64,
+    one: f64,
+    weight: f64,
+}
+
+impl ShapTree {
+
+    pub fn new<T: Tree>(tree: &T) -> ShapTree {
+        let left_children = tree.left_children().clone();
+        let right_children = tree.right_children().clone();
+        let splits = tree.splits();
+        let n = splits.len();
+
+        let count = |k: usize| -> Option<f64> {
+            let summary = splits[k].as_ref().unwrap().get_summary();
+            match summary.iter().all(|x| x.len() > 1) {
+                true => Some(summary.iter().map(|x| x[1] as f64).sum()),
+                false => None
+            }
+        };
+
+        // children always come after their parent
+        let mut cover = vec![0.0; n];
+        cover[0] = count(0).unwrap_or(1.0);
+        for k in 0..n {
+            if let (Some(l), Some(r)) = (left_children[k], right_children[k]) {
+                cover[l] = count(l).unwrap_or(cover[k] / 2.0);
+                cover[r] = count(r).unwrap_or(cover[k] / 2.0);
+            }
+        }
+
+        let split_infos: Vec<&T::T> = splits.iter().map(|x| x.as_ref().unwrap()).collect();
+        ShapTree {
+            feature: split_infos.iter().map(|x| x.get_feature_id()).collect(),
+            iscat: split_infos.iter().map(|x| x.get_iscat()).collect(),
+            value: split_infos.iter().map(|x| x.get_value().unwrap_or_default()).collect(),
+            missing_left: split_infos.iter().map(|x| x.get_missing_left()).collect(),
+            uplift: split_infos.iter().map(|x| node_uplift(*x).iter().map(|u| *u as f64).collect()).collect(),
+            left_children,
+            right_children,
+            cover
+        }
+    }
+
+    // uplift averaged over the leaves by cover, what the attributions of a row are relative to
+    pub fn expected_value(& self) -> Vec<f64> {
+        let n_uplift = self.uplift[0].len();
+        let mut expected = vec![0.0; n_uplift];
+        for k in 0..self.cover.len() {
+            if self.left_children[k].is_some() {
+                continue;
+            }
+
+            for (e, u) in expected.iter_mut().zip(self.uplift[k].iter()) {
+                *e += u * self.cover[k] / self.cover[0];
+            }
+        }
+
+        expected
+    }
+
+    // the child a row takes, the same routing as the partition during training
+    fn hot(& self, n: usize, x: Option<Int>) -> bool {
+        match x {
+            None => self.missing_left[n],
+            Some(x) if self.iscat[n] => self.value[n].contains(&x),
+            Some(x) => x <= self.value[n][0]
+        }
+    }
+
+    /*
+    adds the attributions of a row, binned in the order of the model features, to phi,
+    [feature][uplift]. they add up to the uplift of the row minus the expected value
+    */
+    pub fn shap(& self, row: &[Option<Int>], phi: &mut [Vec<f64>]) {
+        let root = PathElement { feature: usize::MAX, zero: 1.0, one: 1.0, weight: 0.0 };
+        self.recurse(row, 0, &[], root, phi);
+    }
+
+    // element holds the feature of the split leading to n and the fractions of rows following it
+    fn recurse(& self, row: &[Option<Int>], n: usize, path: &[PathElement], element: PathElement, phi: &mut [Vec<f64>]) {
+        let mut path = path.to_vec();
+        extend(&mut path, element.zero, element.one, element.feature);
+
+        let (l, r) = match (self.left_children[n], self.right_children[n]) {
+            (Some(l), Some(r)) => (l, r),
+            _ => {
+                for i in 1..path.len() {
+                    let w = unwound_sum(&path, i);
+                    let e = path[i];
+                    for (p, u) in phi[e.feature].iter_mut().zip(self.uplift[n].iter()) {
+                        *p += w * (e.one - e.zero) * u;
+                    }
+                }
+                return;
+            }
+        };
+
+        let feature = self.feature[n];
+        let (hot, cold) = match self.hot(n, row[feature]) {
+            true => (l, r),
+            false => (r, l)
+        };
+
+        // a feature met again on the path is taken out and its fractions carried over
+        let mut zero = 1.0;
+        let mut one = 1.0;
+        if let Some(k) = (1..path.len()).find(|k| path[*k].feature == feature) {
+            zero = path[k].zero;
+            one = path[k].one;
+            unwind(&mut path, k);
+        }
+
+        let cover = self.cover[n];
+        let fraction = |c: usize| match cover {
+            x if x > 0.0 => self.cover[c] / x,
+            _ => 0.5
+        };
+
+        let hot_element = PathElement { feature, zero: zero * fraction(hot), one, weight: 0.0 };
+        let cold_element = PathElement { feature, zero: zero * fraction(cold), one: 0.0, weight: 0.0 };
+        self.recurse(row, hot, &path, hot_element, phi);
+        self.recurse(row, cold, &path, cold_element, phi);
+    }
+}
+
+fn extend(path: &mut Vec<PathElement>, zero: f64, one: f64, feature: usize) {
+    let l = path.len();
+    let weight = match l {
+        0 => 1.0,
+        _ => 0.0
+    };
+    path.push(PathElement { feature, zero, one, weight });
+
+    for i in (0..l).rev() {
+        path[i + 1].weight += one * path[i].weight * (i + 1) as f64 / (l + 1) as f64;
+        path[i].weight = zero * path[i].weight * (l - i) as f64 / (l + 1) as f64;
+    }
+}
+
+fn unwind(path: &mut Vec<PathElement>, i: usize) {
+    let l = path.len() - 1;
+    let one = path[i].one;
+    let zero = path[i].zero;
+    let mut next = path[l].weight;
+
+    for j in (0..l).rev() {
+        if one != 0.0 {
+            let t = path[j].weight;
+            path[j].weight = next * (l + 1) as f64 / ((j + 1) as f64 * one);
+            next = t - path[j].weight * zero * (l - j) as f64 / (l + 1) as f64;
+        } else {
+            path[j].weight = path[j].weight * (l + 1) as f64 / (zero * (l - j) as f64);
+        }
+    }
+
+    for j in i..l {
+        path[j].feature = path[j + 1].feature;
+        path[j].zero = path[j + 1].zero;
+        path[j].one = path[j + 1].one;
+    }
+    path.pop();
+}
+
+// total weight of the path with element i unwound, without changing the path
+fn unwound_sum(path: &[PathElement], i: usize) -> f64 {
+    let l = path.len() - 1;
+    let one = path[i].one;
+    let zero = path[i].zero;
+    let mut next = path[l].weight;
+    let mut total = 0.0;
+
+    for j in (0..l).rev() {
+        if one != 0.0 {
+            let t = next * (l + 1) as f64 / ((j + 1) as f64 * one);
+            total += t;
+            next = path[j].weight - t * zero * (l - j) as f64 / (l + 1) as f64;
+        } else {
+            total += path[j].weight / zero / ((l - j) as f64 / (l + 1) as f64);
+        }
+    }
+
+    total
+}
+
+/*
+attributions of every row of m to every feature, [row][feature][uplift], averaged over the trees
+*/
+pub fn shap_values(trees: &[ShapTree], m: &DMatrix, rows: std::ops::Range<usize>) -> Vec<Vec<Vec<Float>>> {
+    let n_tree = trees.len() as f64;
+    let n_uplift = trees.first().map(|x| x.uplift[0].len()).unwrap_or(0);
+
+    rows.map(|i| {
+        let row: Vec<Option<Int>> = (0..m.n_feature()).map(|f| m.get(f, i)).collect();
+        let mut phi = vec![vec![0.0; n_uplift]; m.n_feature()];
+        for tree in trees.iter() {
+            tree.shap(&row, &mut phi);
+        }
+
+        phi.iter().map(|x| x.iter().map(|v| (v / n_tree) as Float).collect()).collect()
+    }).collect()
+}
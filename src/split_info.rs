@@ -8,6 +8,7 @@ pub trait SplitInfoTrait {
     fn set_treatment_id(&mut self, treatment_id: usize);
     fn set_iscat(&mut self, iscat: bool);
     fn set_value(&mut self, value: Option<Vec<Int>>);
+    fn set_missing_left(&mut self, missing_left: bool);
     fn set_gain(&mut self, gain: Float);
     fn set_gain_importance(&mut self, gain_importance: Float);
     fn set_summary(&mut self, summary: Vec<Vec<Float>>);
@@ -16,6 +17,7 @@ pub trait SplitInfoTrait {
     fn get_treatment_id(& self) -> usize;
     fn get_iscat(& self) -> bool;
     fn get_value(& self) -> Option<Vec<Int>>;
+    fn get_missing_left(& self) -> bool;
     fn get_gain(& self) -> Float;
     fn get_gain_importance(& self) -> Float;
     fn get_summary(& self) -> Vec<Vec<Float>>;
@@ -29,6 +31,9 @@ pub struct SplitInfo {
     pub iscat: bool,
     #[serde(default)]
     pub value: Option<Vec<Int>>,
+    // missing values are routed to the left child when true, to the right child otherwise
+    #[serde(default)]
+    pub missing_left: bool,
     pub gain: Float,
     pub gain_importance: Float,
     pub summary: Vec<Vec<Float>>
@@ -60,6 +65,10 @@ impl SplitInfoTrait for SplitInfo {
         self.value = value;
     }
 
+    fn set_missing_left(&mut self, missing_left: bool) {
+        self.missing_left = missing_left;
+    }
+
     fn set_gain(&mut self, gain: Float) {
         self.gain = gain;
     }
@@ -94,7 +103,11 @@ impl SplitInfoTrait for SplitInfo {
             None => None
         }
     }
-    
+
+    fn get_missing_left(& self) -> bool {
+        self.missing_left
+    }
+
     fn get_gain(& self) -> Float {
         self.gain
     }
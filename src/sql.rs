@@ -0,0 +1,467 @@
+use crate::config::{Int, Float};
+use crate::bin::EncodeMethod;
+use crate::data_loader::DataLoader;
+use crate::split_info::SplitInfoTrait;
+use crate::tree::Tree;
+use crate::explain::node_uplift;
+
+const INDENT: &str = "    ";
+
+// where the rows with a value, as opposed to a missing one, go at a split
+enum Route {
+    Always(bool),
+    // the rows for which the expression holds go left when the flag is set, right otherwise
+    When(String, bool),
+}
+
+// identifiers are double quoted, as in standard sql
+pub fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+// the exact value of a double, as a literal with a decimal point, so that sql does not take it for an integer
+fn literal(x: f64) -> String {
+    format!("{:?}", x)
+}
+
+/*
+predict rounds every value to a Float before binning it, so a double column holds v for all the
+doubles that round to v. this is the smallest of them, half way to the float below when the tie
+goes to v, the next double otherwise
+*/
+fn lower_bound(v: Float) -> f64 {
+    // below -MAX rounding overflows, as if the next float were -2^128
+    let below = match v {
+        x if x == Float::MIN => -(2.0f64.powi(128)),
+        x => x.next_down() as f64
+    };
+    let mid = (below + v as f64) / 2.0;
+    match mid as Float == v {
+        true => mid,
+        false => mid.next_up()
+    }
+}
+
+// the largest double that rounds to v, rounding being symmetric
+fn upper_bound(v: Float) -> f64 {
+    -lower_bound(-v)
+}
+
+/*
+the bucket of CategoryEncoder::hash, in 64-bit integer arithmetic. it agrees with predict on
+integer codes below 2^24, which a Float holds exactly
+*/
+fn hash(column: &str, n_bucket: usize) -> String {
+    format!("MOD(MOD(MOD(CAST(FLOOR(ABS({})) AS BIGINT), 2147483647) * 48271, 2147483647), {})", column, n_bucket)
+}
+
+// a disjunction nested as a balanced tree, engines limiting the depth of expressions
+fn any(tests: &[String]) -> String {
+    match tests.len() {
+        1 => tests[0].clone(),
+        n => format!("({} OR {})", any(&tests[..n / 2]), any(&tests[n / 2..]))
+    }
+}
+
+/*
+whether expr is one of values: a bucket computed by the hash is an integer, listed as is, while a
+column matches a value when it rounds to it
+*/
+fn one_of(expr: &str, values: &[Float], hashed: bool) -> String {
+    match hashed {
+        true => {
+            let list: Vec<String> = values.iter().map(|x| literal(*x as f64)).collect();
+            format!("{} IN ({})", expr, list.join(", "))
+        },
+        false => {
+            let ranges: Vec<String> = values.iter()
+                .map(|x| format!("{} BETWEEN {} AND {}", expr, literal(lower_bound(*x)), literal(upper_bound(*x))))
+                .collect();
+            any(&ranges)
+        }
+    }
+}
+
+/*
+a test of set membership: the values in left go left, the others right, and values outside
+both, not seen in training, go left when other_left. the side without them is the one listed
+*/
+fn membership(expr: &str, left: &[Float], right: &[Float], other_left: bool, hashed: bool) -> Route {
+    match other_left {
+        true if right.is_empty() => Route::Always(true),
+        true => Route::When(one_of(expr, right, hashed), false),
+        false if left.is_empty() => Route::Always(false),
+        false => Route::When(one_of(expr, left, hashed), true)
+    }
+}
+
+/*
+the route of a split on the original values, the same as the partition of binned rows: a
+continuous bin is the number of thresholds <= x, so bin <= t is x < threshold[t], and a discrete
+bin is the position in the mapping, the last bin holding the values not seen in training
+*/
+fn route(loader: &DataLoader, feature: &str, iscat: bool, value: &[Int]) -> Route {
+    let column = quote(feature);
+    let encoder = loader.encoders.get(feature);
+
+    if iscat {
+        let bin = loader.bins_disc.get(feature).unwrap();
+        let goes_left = |b: usize| value.iter().any(|x| *x as usize == b);
+        let left: Vec<Float> = (0..bin.mapping.len()).filter(|b| goes_left(*b)).map(|b| bin.mapping[b]).collect();
+        let right: Vec<Float> = (0..bin.mapping.len()).filter(|b| !goes_left(*b)).map(|b| bin.mapping[b]).collect();
+        let other_left = goes_left(bin.mapping.len());
+
+        return match encoder {
+            Some(x) if x.method == EncodeMethod::Hash => membership(&hash(&column, x.n_bucket), &left, &right, other_left, true),
+            _ => membership(&column, &left, &right, other_left, false)
+        };
+    }
+
+    let threshold = match loader.bins_cont.get(feature).unwrap().threshold.get(value[0] as usize) {
+        Some(x) => *x,
+        None => return Route::Always(true)
+    };
+
+    match encoder {
+        // categories are binned by their score, the default score standing for unseen ones
+        Some(x) if x.method == EncodeMethod::Uplift => {
+            let left: Vec<Float> = x.keys.iter().zip(x.score.iter()).filter(|(_, s)| **s < threshold).map(|(k, _)| *k).collect();
+            let right: Vec<Float> = x.keys.iter().zip(x.score.iter()).filter(|(_, s)| **s >= threshold).map(|(k, _)| *k).collect();
+            membership(&column, &left, &right, x.default < threshold, false)
+        },
+        // the value is below the threshold when it rounds below it
+        _ => Route::When(format!("{} < {}", column, literal(lower_bound(threshold))), true)
+    }
+}
+
+fn write_node<T: Tree>(tree: &T, loader: &DataLoader, n: usize, arm: usize, depth: usize) -> String {
+    let split_info = tree.splits()[n].as_ref().unwrap();
+    let (l, r) = match (tree.left_children()[n], tree.right_children()[n]) {
+        (Some(l), Some(r)) => (l, r),
+        // an arm without rows in the leaf has no uplift, NULL in sql as NaN in predict
+        _ => return match node_uplift(split_info)[arm] {
+            x if x.is_nan() => "NULL".to_string(),
+            x => literal(x as f64)
+        }
+    };
+
+    let feature = &loader.features[split_info.get_feature_id()];
+    let value = split_info.get_value().unwrap();
+    let indent = INDENT.repeat(depth + 1);
+    let side = |left: bool| match left {
+        true => write_node(tree, loader, l, arm, depth + 1),
+        false => write_node(tree, loader, r, arm, depth + 1)
+    };
+
+    // NULL fails every comparison, so it falls to ELSE unless tested for
+    let column = quote(feature);
+    let missing_left = split_info.get_missing_left();
+    let (test, left) = match route(loader, feature, split_info.get_iscat(), &value) {
+        Route::Always(left) if left == missing_left => {
+            return write_node(tree, loader, if left { l } else { r }, arm, depth);
+        },
+        Route::Always(_) => (format!("{} IS NULL", column), missing_left),
+        Route::When(expr, left) if left == missing_left => (format!("{} IS NULL OR {}", column, expr), left),
+        Route::When(expr, left) => (expr, left)
+    };
+
+    format!("CASE\n{}WHEN {} THEN {}\n{}ELSE {}\n{}END", indent, test, side(left), indent, side(!left), INDENT.repeat(depth))
+}
+
+/*
+the uplift of a tree for one treatment, 0 being the first, as a CASE expression on the original
+columns. missing values are NULL and go where the split sends them
+*/
+pub fn tree_to_sql<T: Tree>(tree: &T, loader: &DataLoader, arm: usize) -> String {
+    write_node(tree, loader, 0, arm, 0)
+}
+
+/*
+a query scoring the rows of table, a table name or a parenthesised subquery: the inner query
+evaluates every tree for every treatment, and the outer one averages them into uplift columns,
+one per treatment against the control, next to the columns of keep
+*/
+pub fn forest_to_sql<T: Tree>(trees: &[&T], loader: &DataLoader, table: &str, keep: &[String]) -> String {
+    let n_uplift = trees.first()
+        .map(|x| node_uplift(x.splits()[0].as_ref().unwrap()).len())
+        .unwrap_or(0);
+    let name = |k: usize| match n_uplift {
+        1 => "uplift".to_string(),
+        _ => format!("uplift_{}", k + 1)
+    };
+
+    let mut inner: Vec<String> = keep.iter().map(|x| format!("{}{}", INDENT.repeat(2), quote(x))).collect();
+    for (t, tree) in trees.iter().enumerate() {
+        for k in 0..n_uplift {
+            let case = tree_to_sql(*tree, loader, k).replace('\n', &format!("\n{}", INDENT.repeat(2)));
+            inner.push(format!("{}{} AS {}", INDENT.repeat(2), case, quote(&format!("tree_{}_{}", t, k + 1))));
+        }
+    }
+
+    let mut outer: Vec<String> = keep.iter().map(|x| format!("{}{}", INDENT, quote(x))).collect();
+    for k in 0..n_uplift {
+        let terms: Vec<String> = (0..trees.len()).map(|t| quote(&format!("tree_{}_{}", t, k + 1))).collect();
+        outer.push(format!("{}({}) / {:?} AS {}", INDENT, terms.join(" + "), trees.len() as f64, quote(&name(k))));
+    }
+
+    format!("SELECT\n{}\nFROM (\n{}SELECT\n{}\n{}FROM {}\n) AS trees\n",
+        outer.join(",\n"), INDENT, inner.join(",\n"), INDENT, table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture;
+    use crate::rf::RandomForest;
+    use crate::tree::{ClassificationTree, RegressionTree};
+    use crate::kl::KLStrategy;
+    use crate::regression::RegressionStrategy;
+    use crate::strategy::Strategy;
+
+    #[derive(Clone, Debug, PartialEq)]
+    enum Token {
+        Name(String),
+        Word(String),
+        Number(f64),
+        Punct(char),
+    }
+
+    fn tokenize(sql: &str) -> Vec<Token> {
+        let chars: Vec<char> = sql.chars().collect();
+        let mut tokens: Vec<Token> = Vec::new();
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            let start = i;
+            i += 1;
+            match c {
+                x if x.is_whitespace() => {},
+                '"' => {
+                    while chars[i] != '"' {
+                        i += 1;
+                    }
+                    tokens.push(Token::Name(chars[start + 1..i].iter().collect()));
+                    i += 1;
+                },
+                x if x.is_ascii_digit() || x == '-' => {
+                    while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '.'
+                        || (chars[i] == '-' && chars[i - 1] == 'e')) {
+                        i += 1;
+                    }
+                    tokens.push(Token::Number(chars[start..i].iter().collect::<String>().parse().unwrap()));
+                },
+                x if x.is_ascii_alphabetic() => {
+                    while i < chars.len() && chars[i].is_ascii_alphabetic() {
+                        i += 1;
+                    }
+                    tokens.push(Token::Word(chars[start..i].iter().collect()));
+                },
+                x => tokens.push(Token::Punct(x))
+            }
+        }
+        tokens
+    }
+
+    /*
+    an evaluator of the sql the trees are written in, on one row of doubles, NULL being None. both
+    sides of a CASE are evaluated, which is enough to read past them
+    */
+    struct Eval<'a> {
+        tokens: Vec<Token>,
+        pos: usize,
+        row: &'a dyn Fn(&str) -> Option<f64>,
+    }
+
+    impl<'a> Eval<'a> {
+
+        fn next(&mut self) -> Token {
+            self.pos += 1;
+            self.tokens[self.pos - 1].clone()
+        }
+
+        fn expect(&mut self, token: Token) {
+            assert_eq!(self.next(), token);
+        }
+
+        fn word(&mut self, word: &str) -> bool {
+            match self.tokens.get(self.pos) {
+                Some(Token::Word(x)) if x == word => {
+                    self.pos += 1;
+                    true
+                },
+                _ => false
+            }
+        }
+
+        fn args(&mut self, n: usize) -> Vec<Option<f64>> {
+            self.expect(Token::Punct('('));
+            let mut args = vec![self.expr()];
+            for _ in 1..n {
+                self.expect(Token::Punct(','));
+                args.push(self.expr());
+            }
+            self.expect(Token::Punct(')'));
+            args
+        }
+
+        fn expr(&mut self) -> Option<f64> {
+            let mut x = self.term();
+            while self.tokens.get(self.pos) == Some(&Token::Punct('*')) {
+                self.pos += 1;
+                let y = self.term();
+                x = x.zip(y).map(|(a, b)| a * b);
+            }
+            x
+        }
+
+        fn term(&mut self) -> Option<f64> {
+            let word = match self.next() {
+                Token::Number(x) => return Some(x),
+                Token::Name(x) => return (self.row)(&x),
+                Token::Word(x) => x,
+                x => panic!("unexpected {:?}", x)
+            };
+
+            match word.as_str() {
+                "NULL" => None,
+                "CASE" => {
+                    self.expect(Token::Word("WHEN".to_string()));
+                    let test = self.cond();
+                    self.expect(Token::Word("THEN".to_string()));
+                    let then = self.expr();
+                    self.expect(Token::Word("ELSE".to_string()));
+                    let other = self.expr();
+                    self.expect(Token::Word("END".to_string()));
+                    if test { then } else { other }
+                },
+                "MOD" => {
+                    let args = self.args(2);
+                    args[0].zip(args[1]).map(|(a, b)| a % b)
+                },
+                "FLOOR" => self.args(1)[0].map(|x| x.floor()),
+                "ABS" => self.args(1)[0].map(|x| x.abs()),
+                "CAST" => {
+                    self.expect(Token::Punct('('));
+                    let x = self.expr();
+                    self.expect(Token::Word("AS".to_string()));
+                    self.expect(Token::Word("BIGINT".to_string()));
+                    self.expect(Token::Punct(')'));
+                    x.map(|v| v.trunc())
+                },
+                x => panic!("unexpected {}", x)
+            }
+        }
+
+        fn cond(&mut self) -> bool {
+            let mut test = self.pred();
+            while self.word("OR") {
+                test = self.pred() || test;
+            }
+            test
+        }
+
+        fn pred(&mut self) -> bool {
+            if self.tokens[self.pos] == Token::Punct('(') {
+                self.pos += 1;
+                let test = self.cond();
+                self.expect(Token::Punct(')'));
+                return test;
+            }
+
+            let x = self.expr();
+            if self.word("IS") {
+                self.expect(Token::Word("NULL".to_string()));
+                return x.is_none();
+            }
+            if self.word("BETWEEN") {
+                let lo = self.expr();
+                self.expect(Token::Word("AND".to_string()));
+                let hi = self.expr();
+                return matches!((x, lo, hi), (Some(v), Some(a), Some(b)) if a <= v && v <= b);
+            }
+            if self.word("IN") {
+                self.expect(Token::Punct('('));
+                let mut list = vec![self.expr()];
+                while self.next() == Token::Punct(',') {
+                    list.push(self.expr());
+                }
+                return x.is_some() && list.contains(&x);
+            }
+
+            self.expect(Token::Punct('<'));
+            let y = self.expr();
+            matches!((x, y), (Some(a), Some(b)) if a < b)
+        }
+    }
+
+    fn evaluate(sql: &str, headers: &[String], row: &[Option<f64>]) -> Option<f64> {
+        let lookup = |name: &str| row[headers.iter().position(|x| x == name).unwrap()];
+        let mut eval = Eval { tokens: tokenize(sql), pos: 0, row: &lookup };
+        let value = eval.expr();
+        assert_eq!(eval.pos, eval.tokens.len());
+        value
+    }
+
+    /*
+    every tree scores the rows in sql as predict does, bin edges included: the rows are on the grid
+    of the training values, which the thresholds are taken from, with categories not seen in
+    training and values just off the bounds of the thresholds
+    */
+    fn check_parity<T, S>(y_col: &str)
+        where T: Tree + Clone + Send + 'static, S: Strategy + Clone + Send + 'static {
+
+        let headers = fixture::headers();
+        let train = fixture::data(4000, 1);
+        let mut rf: RandomForest<T, S> = RandomForest::new(fixture::conf(y_col, 4));
+        let m = rf.loader.from_memory(&headers, &Vec::new(), &fixture::to_float(&train));
+        rf.fit(m);
+
+        let mut rows = fixture::data(2000, 2);
+        for (k, row) in rows.iter_mut().enumerate().take(200) {
+            row[2] = Some([0.9, 0.15, -1.0][k % 3]);
+        }
+        let threshold = &rf.loader.bins_cont.get("x0").unwrap().threshold;
+        for t in threshold.iter() {
+            for x in [lower_bound(*t), lower_bound(*t).next_down(), upper_bound(*t), upper_bound(*t).next_up()] {
+                let mut row = rows[0].clone();
+                row[0] = Some(x);
+                rows.push(row);
+            }
+        }
+
+        let m = rf.loader.from_memory(&headers, &Vec::new(), &fixture::to_float(&rows));
+        let n_feature = rf.loader.features.len();
+        for learn in rf.learners.iter() {
+            let sql = tree_to_sql(&learn.tree, &rf.loader, 0);
+            for (i, row) in rows.iter().enumerate() {
+                let binned: Vec<Option<Int>> = (0..n_feature).map(|f| m.get(f, i)).collect();
+                let expected = learn.predict_row(&binned)[0];
+                match evaluate(&sql, &headers, row) {
+                    Some(x) => assert_eq!(x as Float, expected, "row {:?}", row),
+                    None => assert!(expected.is_nan(), "row {:?}", row)
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_bounds() {
+        for v in [19.99, 0.1, 0.0, -3.7, 1.0, Float::MAX, Float::MIN, Float::MIN_POSITIVE] {
+            for x in [lower_bound(v), upper_bound(v)] {
+                assert_eq!(x as Float, v);
+            }
+            assert_ne!(lower_bound(v).next_down() as Float, v);
+            assert_ne!(upper_bound(v).next_up() as Float, v);
+        }
+    }
+
+    #[test]
+    fn test_parity_classification() {
+        check_parity::<ClassificationTree, KLStrategy>("y");
+    }
+
+    #[test]
+    fn test_parity_regression() {
+        check_parity::<RegressionTree, RegressionStrategy>("yr");
+    }
+}
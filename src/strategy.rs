@@ -1,12 +1,20 @@
 use crate::dmatrix::DMatrix;
 use crate::split_info::SplitInfoTrait;
-use crate::config::TreeConfig;
+use crate::config::{Float, TreeConfig};
+use crate::histogram::Histogram;
 
-pub trait Strategy {
-    type T: Clone + SplitInfoTrait;
+// strategies are shared by the threads searching the features of a node
+pub trait Strategy: Sync {
+    type T: Clone + SplitInfoTrait + Send;
 
     fn new(conf: &TreeConfig) -> Self;
 
-    fn find_best_split(& self, m: &DMatrix, node_id: usize, feature_id: usize, 
-        indices: &mut [usize]) -> Self::T;
+    // number of values a row adds to its bin of a histogram
+    fn n_channel(& self) -> usize;
+
+    // writes the value of every channel for a response and a weight
+    fn row_stat(& self, y: Float, weight: Float, value: &mut [Float]);
+
+    fn find_best_split_hist(& self, m: &DMatrix, node_id: usize, feature_id: usize, 
+        hist: &Histogram) -> Self::T;
 }
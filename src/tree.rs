@@ -13,6 +13,11 @@ pub trait Tree {
     fn get_right_children(&mut self) -> &mut Vec<Option<usize>>;
     fn get_split(&mut self) -> &mut Vec<Option<Self::T>>;
 
+    // read-only views of the nodes, for walking a fitted tree
+    fn left_children(& self) -> &Vec<Option<usize>>;
+    fn right_children(& self) -> &Vec<Option<usize>>;
+    fn splits(& self) -> &Vec<Option<Self::T>>;
+
     fn add_split(& mut self, node: usize, split_info: Self::T) {
         if let Some(x) = self.get_split().get_mut(node) {
             *x = Some(split_info);
@@ -75,7 +80,9 @@ pub trait Tree {
         self.get_level().len()
     }
 
-    fn from_string(s: &String) -> Self;
+    fn name() -> &'static str;
+
+    fn from_string(s: &str) -> Result<Self, serde_json::Error> where Self: Sized;
 
     fn to_string(& self) -> String;
 
@@ -106,6 +113,10 @@ pub struct ClassificationTree {
 impl Tree for ClassificationTree {
     type T = SplitInfo;
 
+    fn name() -> &'static str {
+        "ClassificationTree"
+    }
+
     fn new() -> ClassificationTree {
         let level = vec![0; 1];
         let mut left_children = Vec::new();
@@ -125,8 +136,8 @@ impl Tree for ClassificationTree {
         }
     }
 
-    fn from_string(s: &String) -> Self {
-        serde_json::from_str(s).unwrap()
+    fn from_string(s: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(s)
     }
 
     fn to_string(& self) -> String {
@@ -149,6 +160,18 @@ impl Tree for ClassificationTree {
         & mut self.split
     }
 
+    fn left_children(& self) -> &Vec<Option<usize>> {
+        & self.left_children
+    }
+
+    fn right_children(& self) -> &Vec<Option<usize>> {
+        & self.right_children
+    }
+
+    fn splits(& self) -> &Vec<Option<Self::T>> {
+        & self.split
+    }
+
 }
 
 #[derive(Deserialize, Serialize, Clone)]
@@ -162,6 +185,10 @@ pub struct RegressionTree {
 impl Tree for RegressionTree {
     type T = SplitInfo;
 
+    fn name() -> &'static str {
+        "RegressionTree"
+    }
+
     fn new() -> RegressionTree {
         let level = vec![0; 1];
         let mut left_children = Vec::new();
@@ -181,8 +208,8 @@ impl Tree for RegressionTree {
         }
     }
 
-    fn from_string(s: &String) -> Self {
-        serde_json::from_str(s).unwrap()
+    fn from_string(s: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(s)
     }
 
     fn to_string(& self) -> String {
@@ -204,4 +231,16 @@ impl Tree for RegressionTree {
     fn get_split(&mut self) -> &mut Vec<Option<Self::T>> {
         & mut self.split
     }
+
+    fn left_children(& self) -> &Vec<Option<usize>> {
+        & self.left_children
+    }
+
+    fn right_children(& self) -> &Vec<Option<usize>> {
+        & self.right_children
+    }
+
+    fn splits(& self) -> &Vec<Option<Self::T>> {
+        & self.split
+    }
 }